@@ -1,5 +1,7 @@
 use std::path::Path;
 use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::io;
 use std::fs;
 use std::fs::File;
@@ -12,12 +14,18 @@ use super::color::BakedColorManager;
 use super::render;
 use super::render::RenderOptions;
 use super::render::tile::Tile;
+use super::render::data::TILESIZE;
+use super::encoder::TileEncoder;
 
 pub struct AppOptions {
     render_options: RenderOptions,  
     input_folder: PathBuf,
     output_folder: PathBuf,
     thread_num: usize,
+    color_override: Option<PathBuf>,
+    tint_rules: Option<PathBuf>,
+    encoder: TileEncoder,
+    profile: Option<String>,
 }
 
 impl Default for AppOptions {
@@ -26,7 +34,11 @@ impl Default for AppOptions {
             render_options: Default::default(),
             input_folder: Default::default(),
             output_folder: Default::default(),
-            thread_num: 1
+            thread_num: 1,
+            color_override: None,
+            tint_rules: None,
+            encoder: TileEncoder::default(),
+            profile: None,
         }
     }
 }
@@ -49,6 +61,23 @@ impl AppOptions {
         self.output_folder = PathBuf::from(path);
     }
 
+    pub fn set_color_override(&mut self, path: &str) {
+        self.color_override = Some(PathBuf::from(path));
+    }
+
+    pub fn set_tint_rules(&mut self, path: &str) {
+        self.tint_rules = Some(PathBuf::from(path));
+    }
+
+    pub fn set_encoder(&mut self, encoder: TileEncoder) {
+        self.encoder = encoder;
+    }
+
+    /// resources in `resource/<profile>/` instead of `resource/`
+    pub fn set_profile(&mut self, profile: &str) {
+        self.profile = Some(String::from(profile));
+    }
+
     pub fn ensure_output_folder(&self) -> io::Result<()> {
         if !self.output_folder.is_dir() {
             std::fs::create_dir_all(self.output_folder.as_path())
@@ -72,11 +101,35 @@ pub struct RenderTask {
 
 impl Application {
 
-    pub fn new(options: AppOptions) -> Self {
-        Application {
-            color_mgr: build_colormanager(),
-            options
+    /// the error of an unknown profile, invalid resources, or an invalid color override or tint rules file
+    pub fn new(options: AppOptions) -> Result<Self, String> {
+        let mut color_mgr = match &options.profile {
+            Some(profile) => {
+                let dir = profile_dir(profile.as_str())
+                    .ok_or_else(|| format!("unknown resource profile: {}", profile))?;
+                build_colormanager_from(dir.as_path())
+                    .map_err(|e| format!("invalid resources {}: {}", dir.display(), e))?
+            }
+            None => build_colormanager().map_err(|e| format!("invalid resources: {}", e))?,
+        };
+        if let Some(path) = &options.color_override {
+            let overrides = File::open(path)
+                .map_err(|e| e.to_string())
+                .and_then(de::build_color_overrides)
+                .map_err(|e| format!("invalid color override {}: {}", path.display(), e))?;
+            color_mgr.add_overrides(overrides);
         }
+        if let Some(path) = &options.tint_rules {
+            let rules = File::open(path)
+                .map_err(|e| e.to_string())
+                .and_then(de::build_tint_rules)
+                .map_err(|e| format!("invalid tint rules {}: {}", path.display(), e))?;
+            color_mgr.add_tint_rules(rules);
+        }
+        Ok(Application {
+            color_mgr,
+            options
+        })
     }
 
     pub fn list_files(&self) -> Vec<RenderTask> {
@@ -93,7 +146,7 @@ impl Application {
                 let mut sp = filename.splitn(2, ',');
                 let x: i32 = sp.next()?.parse().ok()?;
                 let z: i32 = sp.next()?.parse().ok()?;
-                let tgt = odir.join(format!("{},{}.png", x, z));
+                let tgt = odir.join(format!("{},{}.{}", x, z, self.options.encoder.extension()));
                 Some(RenderTask{src, tgt, tile_id: (x, z)})
             }).collect()
         } else {
@@ -102,12 +155,44 @@ impl Application {
     }
 
     pub fn render_one(&self, src: &Path, tgt: &Path, tile_id: &(i32, i32)) -> Result<(), Box<dyn std::error::Error>> {
-        use image::ImageFormat::Png;
-
         let ifile = File::open(src).map_err(Box::new)?; 
         let tile = Tile::load(ifile, tile_id.clone(), &self.color_mgr)?;
         let pic = render::render(tile, &self.color_mgr, &self.options.render_options);
-        pic.save_with_format(tgt, Png).map_err(error_trans)
+        let data = self.options.encoder.encode(&pic).map_err(error_trans)?;
+        fs::write(tgt, data).map_err(Box::new)?;
+        Ok(())
+    }
+
+    pub fn color_manager(&self) -> &BakedColorManager {
+        &self.color_mgr
+    }
+
+    /// count the columns in one tile covered by blocks without color; columns are counted once per block name
+    pub fn diagnose_one(&self, src: &Path, tile_id: &(i32, i32), columns: &mut BTreeMap<String, usize>) -> Result<(), Box<dyn std::error::Error>> {
+        let ifile = File::open(src).map_err(Box::new)?; 
+        let tile = Tile::load(ifile, *tile_id, &self.color_mgr)?;
+        let diagnostics = self.color_mgr.diagnostics();
+        let view = tile.view();
+        let mut names = BTreeSet::new();
+        for x in 0 .. TILESIZE.0 {
+            for z in 0 .. TILESIZE.1 {
+                let element = view.element(x, z);
+                let layers = [view.surface(element), view.seafloor(element), view.transparent(element), view.foliage(element)];
+                names.clear();
+                for layer in layers.iter() {
+                    if view.height(*layer) > 0 {
+                        let (c, props) = tile.get_color(view.blockstate_id(*layer));
+                        if c[3] == 0 && !props.air && diagnostics.contains(props.name.as_str()) {
+                            names.insert(props.name.as_str());
+                        }
+                    }
+                }
+                for name in names.iter() {
+                    *columns.entry(String::from(*name)).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(())
     }
 
     pub fn alloc_tasks(this: Arc<Self> ,mut tasks: Vec<RenderTask>) {
@@ -156,24 +241,77 @@ pub fn curdir() -> PathBuf {
     }
 }
 
-pub fn build_colormanager() -> BakedColorManager {
-    
-    use std::io::BufReader;
-    
-    let mut dir = curdir();
-    dir.push("resource");
-    let biome_color = {
-        let r4 = File::open(dir.join("biome.json")).unwrap();
-        let r5 = File::open(dir.join("grass.png")).unwrap();
-        let r6 = File::open(dir.join("foliage.png")).unwrap();
-        de::build_biomecolor(r4, BufReader::new(r5), BufReader::new(r6))
-    };
-    {
-        let r1 = File::open(dir.join("index.json")).unwrap();
-        let r2 = File::open(dir.join("colormap.png")).unwrap();
-        let r3 = File::open(dir.join("weightmap.png")).unwrap();
-        de::build_backedcolormanager(r1, BufReader::new(r2), BufReader::new(r3), biome_color)
+pub fn resource_dir() -> PathBuf {
+    curdir().join("resource")
+}
+
+/// profile names are letters, digits, `-`, `_` and `.`, not starting with `.`
+pub fn valid_profile(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// `resource/<name>/` if the name is valid and the folder has an `index.json`
+pub fn profile_dir(name: &str) -> Option<PathBuf> {
+    let dir = resource_dir().join(name);
+    if valid_profile(name) && dir.join("index.json").is_file() {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+pub fn build_colormanager() -> Result<BakedColorManager, String> {
+    build_colormanager_from(resource_dir().as_path())
+}
+
+/// files of a resource folder, the last two are optional
+pub const RESOURCE_FILES: [&str; 8] = [
+    "biome.json", "grass.png", "foliage.png", "index.json", "colormap.png", "weightmap.png", "override.json", "rules.json",
+];
+
+/// contents of the `RESOURCE_FILES` of `dir` in the same order, `None` for a missing optional file
+pub fn read_resource_files(dir: &Path) -> Result<Vec<Option<Vec<u8>>>, String> {
+    RESOURCE_FILES.iter().enumerate()
+        .map(|(i, name)| match fs::read(dir.join(name)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if i >= 6 && e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", name, e)),
+        })
+        .collect()
+}
+
+/// the error of a required file missing or invalid; invalid optional files are only reported
+pub fn build_colormanager_from(dir: &Path) -> Result<BakedColorManager, String> {
+    build_colormanager_with(read_resource_files(dir)?.as_slice())
+}
+
+/// the color manager of the contents returned by `read_resource_files`
+pub fn build_colormanager_with(files: &[Option<Vec<u8>>]) -> Result<BakedColorManager, String> {
+
+    use std::io::Cursor;
+
+    let file = |i: usize| files.get(i).and_then(Option::as_deref)
+        .ok_or_else(|| format!("{}: missing", RESOURCE_FILES[i]));
+    let biome_color = de::build_biomecolor(
+        Cursor::new(file(0)?), Cursor::new(file(1)?), Cursor::new(file(2)?))?;
+    let mut mgr = de::build_backedcolormanager(
+        Cursor::new(file(3)?), Cursor::new(file(4)?), Cursor::new(file(5)?), biome_color)?;
+    if let Ok(r7) = file(6) {
+        match de::build_color_overrides(Cursor::new(r7)) {
+            Ok(overrides) => mgr.add_overrides(overrides),
+            Err(e) => log::warn!("invalid color override: {}", e),
+        }
+    }
+    if let Ok(r8) = file(7) {
+        match de::build_tint_rules(Cursor::new(r8)) {
+            Ok(rules) => mgr.add_tint_rules(rules),
+            Err(e) => log::warn!("invalid tint rules: {}", e),
+        }
     }
+    Ok(mgr)
 }
 
 fn error_trans(e: image::ImageError) -> Box<dyn std::error::Error> {
@@ -1,6 +1,7 @@
+use std::collections::HashMap;
+
 use image::Rgb;
 use image::RgbImage;
-use image::DynamicImage;
 
 #[derive(Debug, Clone)]
 pub struct Biome(pub usize);
@@ -73,9 +74,20 @@ impl BiomeProps {
     }
 }
 
+pub type BiomeEntry = (String, BiomeProps, Rgb<u8>, BiomeColorTOps, BiomeColorTOps);
+
+/**
+ * biome table by id; ids may be sparse, unknown ids use the entry of id 0 (or the first entry)
+ */
 pub struct BiomeColor {
 
-    biomes: Vec<(String, BiomeProps, Rgb<u8>, BiomeColorTOps, BiomeColorTOps)>,
+    biomes: Vec<BiomeEntry>,
+
+    ids: Vec<Option<usize>>,
+
+    names: HashMap<String, usize>,
+
+    fallback: usize,
 
     grass: RgbImage,
 
@@ -86,28 +98,54 @@ pub struct BiomeColor {
 impl BiomeColor {
 
     #[inline]
-    fn get<'a, T>(vec: &'a Vec<T>, biome: &Biome) -> &'a T {
-        if biome.0 < vec.len() {
-            &vec[biome.0]
-        } else {
-            &vec[0]
-        }
+    fn get(&self, biome: &Biome) -> &BiomeEntry {
+        let i = self.ids.get(biome.0).cloned().flatten().unwrap_or(self.fallback);
+        &self.biomes[i]
     }
 
-    pub fn from_raw(biomes: Vec<(String, BiomeProps, Rgb<u8>, BiomeColorTOps, BiomeColorTOps)>, grass: RgbImage, foliage: RgbImage) -> Self {
+    /// `biomes` must not be empty; a duplicated id replaces the former one
+    pub fn from_raw(biomes: Vec<(usize, BiomeEntry)>, grass: RgbImage, foliage: RgbImage) -> Self {
+        let mut ids = Vec::new();
+        let mut names = HashMap::new();
+        let mut entries = Vec::with_capacity(biomes.len());
+        for (id, entry) in biomes {
+            if ids.len() <= id {
+                ids.resize(id + 1, None);
+            }
+            names.insert(entry.0.clone(), id);
+            ids[id] = Some(entries.len());
+            entries.push(entry);
+        }
+        let fallback = ids.first().cloned().flatten().unwrap_or(0);
         BiomeColor {
-            biomes,
+            biomes: entries,
+            ids,
+            names,
+            fallback,
             grass,
             foliage,
         }
     }
 
+    /// find biome by name, `minecraft:` is assumed if the namespace is omitted
+    pub fn find(&self, name: &str) -> Option<Biome> {
+        if let Some(id) = self.names.get(name) {
+            return Some(Biome(*id));
+        }
+        if !name.contains(':') {
+            if let Some(id) = self.names.get(format!("minecraft:{}", name).as_str()) {
+                return Some(Biome(*id));
+            }
+        }
+        None
+    }
+
     pub fn get_water(&self, biome: &Biome) -> Rgb<u8> {
-        BiomeColor::get(&self.biomes, biome).2.clone()
+        self.get(biome).2.clone()
     }
 
     pub fn get_grass(&self, biome: &Biome, height: i32) -> Rgb<u8> {
-        let t = BiomeColor::get(&self.biomes, biome);
+        let t = self.get(biome);
         let BiomeProps { temperature, rainfall } = t.1.adjust(height);
         let w = (self.grass.width() - 1) as f32;
         let h = (self.grass.height() - 1) as f32;
@@ -118,7 +156,7 @@ impl BiomeColor {
     }
 
     pub fn get_foliage(&self, biome: &Biome, height: i32) -> Rgb<u8> {
-        let t = BiomeColor::get(&self.biomes, biome);
+        let t = self.get(biome);
         let BiomeProps { temperature, rainfall } = t.1.adjust(height);
         let w = (self.foliage.width() - 1) as f32;
         let h = (self.foliage.height() - 1) as f32;
@@ -130,6 +168,7 @@ impl BiomeColor {
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InnerColor {
     None,
     Water,
@@ -137,19 +176,43 @@ pub enum InnerColor {
     Foliage,
 }
 
-impl From<&str> for InnerColor {
-
-    fn from(value: &str) -> Self {
-        match value {
-            "minecraft:water" => Self::Water,
-            "minecraft:grass_block" => Self::Grass,
-            "minecraft:lily_pad" => Self::Grass,
-            _ => {
-                if value.ends_with("leaves") {
-                    return Self::Foliage;
-                }
-                Self::None
-            }
-        }
+
+mod test {
+
+    #[test]
+    fn test_sparse_biomes() {
+        use std::io::Cursor;
+        use image::DynamicImage;
+        use image::ImageFormat;
+        use image::Rgb;
+        use image::RgbImage;
+        use super::Biome;
+        use crate::color::de::build_biomecolor;
+
+        let png = |color: [u8; 3]| {
+            let mut buf = Vec::new();
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb(color))).write_to(&mut buf, ImageFormat::Png).unwrap();
+            Cursor::new(buf)
+        };
+        let json = r#"[
+            { "id": 1, "name": "minecraft:plains", "temperature": 0.8, "rainfall": 0.4, "watercolor": 4159204 },
+            { "id": 40, "name": "minecraft:small_end_islands", "temperature": 0.5, "rainfall": 0.5, "watercolor": 16711680 },
+            { "id": 7, "name": "minecraft:river", "temperature": 0.5, "rainfall": 0.5, "watercolor": 255 }
+        ]"#;
+        let biomes = build_biomecolor(json.as_bytes(), png([0, 255, 0]), png([0, 128, 0])).unwrap();
+        assert_eq!(biomes.find("minecraft:river").map(|b| b.0), Some(7));
+        assert_eq!(biomes.find("small_end_islands").map(|b| b.0), Some(40));
+        assert!(biomes.find("minecraft:ocean").is_none());
+        assert!(biomes.find("other:river").is_none());
+        assert_eq!(biomes.get_water(&Biome(7)), Rgb([0, 0, 255]));
+        assert_eq!(biomes.get_water(&Biome(40)), Rgb([255, 0, 0]));
+        // no id 0, unknown ids use the first entry
+        assert_eq!(biomes.get_water(&Biome(0)), Rgb([0x3f, 0x76, 0xe4]));
+        assert_eq!(biomes.get_water(&Biome(20)), Rgb([0x3f, 0x76, 0xe4]));
+        assert_eq!(biomes.get_water(&Biome(1000)), Rgb([0x3f, 0x76, 0xe4]));
+        assert_eq!(biomes.get_grass(&Biome(1000), 64), Rgb([0, 255, 0]));
+
+        assert!(build_biomecolor("[]".as_bytes(), png([0, 0, 0]), png([0, 0, 0])).is_err());
     }
+
 }
@@ -70,6 +70,14 @@ impl<K: std::cmp::Ord, V> Expression<K, V, usize> {
         (self.keys.len(), self.values.len())
     }
 
+    pub fn keys(&self) -> &Map<K, usize> {
+        &self.keys
+    }
+
+    pub fn values(&self) -> &Map<usize, V> {
+        &self.values
+    }
+
     pub fn get<'a, Q: 'a, I>(&'a self, key: I, strict: bool) -> Option<&'a V> 
     where
         Q: std::cmp::Ord,
@@ -10,6 +10,8 @@ use serde::de::Deserializer;
 use serde::de::MapAccess;
 use serde::de::Visitor;
 use serde::Deserialize;
+use serde::ser::SerializeMap;
+use serde::ser::Serializer;
 use serde::Serialize;
 use serde_json;
 
@@ -22,6 +24,7 @@ use image::Rgb;
 use image::Rgba;
 
 use super::biome::BiomeColor;
+use super::biome::BiomeEntry;
 use super::biome::BiomeColorTOps;
 use super::biome::BiomeProps;
 use super::BakedColorManager;
@@ -62,14 +65,17 @@ struct BiomeTupleRaw {
     ops_foliage: Option<BiomeColorTOpsRaw>,
 }
 
-impl Into<(String, BiomeProps, Rgb<u8>, BiomeColorTOps, BiomeColorTOps)> for BiomeTupleRaw {
-    fn into(self) -> (String, BiomeProps, Rgb<u8>, BiomeColorTOps, BiomeColorTOps) {
+impl Into<(usize, BiomeEntry)> for BiomeTupleRaw {
+    fn into(self) -> (usize, BiomeEntry) {
         (
-            self.name,
-            BiomeProps::new(self.temperature, self.rainfall),
-            u32_to_rgb(self.watercolor),
-            raw2ops(self.ops_grass),
-            raw2ops(self.ops_foliage),
+            self.id,
+            (
+                self.name,
+                BiomeProps::new(self.temperature, self.rainfall),
+                u32_to_rgb(self.watercolor),
+                raw2ops(self.ops_grass),
+                raw2ops(self.ops_foliage),
+            ),
         )
     }
 }
@@ -78,34 +84,28 @@ pub fn build_biomecolor<R: Read, RI: BufRead + Seek>(
     biome_data: R,
     grass_colormap: RI,
     foliage_colormap: RI,
-) -> BiomeColor {
-    let raws: Vec<BiomeTupleRaw> = serde_json::from_reader(biome_data).unwrap();
-    let biomes = raws
-        .into_iter()
-        .enumerate()
-        .map(|(i, e)| {
-            if i == e.id {
-                e.into()
-            } else {
-                panic!("invalid biome data: {}", e.name)
-            }
-        })
-        .collect();
-    let grass = if let DynamicImage::ImageRgb8(img) =
-        image::load(grass_colormap, ImageFormat::Png).unwrap()
+) -> Result<BiomeColor, String> {
+    let raws: Vec<BiomeTupleRaw> =
+        serde_json::from_reader(biome_data).map_err(|e| format!("invalid biome data: {}", e))?;
+    if raws.is_empty() {
+        return Err(String::from("invalid biome data: empty"));
+    }
+    let biomes = raws.into_iter().map(Into::into).collect();
+    let grass = if let DynamicImage::ImageRgb8(img) = image::load(grass_colormap, ImageFormat::Png)
+        .map_err(|e| format!("invalid image: grass: {}", e))?
     {
         img
     } else {
-        panic!("invalid image format: grass");
+        return Err(String::from("invalid image format: grass"));
     };
-    let foliage = if let DynamicImage::ImageRgb8(img) =
-        image::load(foliage_colormap, ImageFormat::Png).unwrap()
+    let foliage = if let DynamicImage::ImageRgb8(img) = image::load(foliage_colormap, ImageFormat::Png)
+        .map_err(|e| format!("invalid image: foliage: {}", e))?
     {
         img
     } else {
-        panic!("invalid image format: grass");
+        return Err(String::from("invalid image format: foliage"));
     };
-    BiomeColor::from_raw(biomes, grass, foliage)
+    Ok(BiomeColor::from_raw(biomes, grass, foliage))
 }
 
 /**
@@ -302,6 +302,55 @@ pub type BlockStateC = BlockState<String, usize>;
 
 blockstate_deserialize!(String, usize);
 
+#[derive(Serialize)]
+struct VariantSer<'a> {
+    keys: &'a Map<String, usize>,
+    values: Map<String, usize>,
+}
+
+#[derive(Serialize)]
+struct MultiPartElementSer {
+    when: Vec<usize>,
+    apply: usize,
+}
+
+#[derive(Serialize)]
+struct MultiPartSer<'a> {
+    keys: &'a Map<String, usize>,
+    values: Vec<MultiPartElementSer>,
+}
+
+impl Serialize for BlockStateC {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(1))?;
+        match self {
+            BlockState::Single(m) => {
+                map.serialize_entry("single", m)?;
+            }
+            BlockState::Variants(expr) => {
+                let values = expr.values().iter().map(|(k, v)| (k.to_string(), *v)).collect();
+                map.serialize_entry("variants", &VariantSer { keys: expr.keys(), values })?;
+            }
+            BlockState::MultiPart(expr, _) => {
+                // parts are rebuilt by their applied model, so every part needs a distinct model
+                let mut values: Vec<MultiPartElementSer> = Vec::new();
+                for (when, apply) in expr.values() {
+                    if let Some(part) = values.iter_mut().find(|part| part.apply == *apply) {
+                        part.when.push(*when);
+                    } else {
+                        values.push(MultiPartElementSer { when: vec![*when], apply: *apply });
+                    }
+                }
+                map.serialize_entry("multipart", &MultiPartSer { keys: expr.keys(), values })?;
+            }
+        }
+        map.end()
+    }
+}
+
 #[derive(Deserialize)]
 struct IndexRaw {
     data: HashMap<String, BlockStateC>,
@@ -312,21 +361,130 @@ pub fn build_backedcolormanager<R: Read, RI: BufRead + Seek>(
     colormap_file: RI,
     weightmap_file: RI,
     biome_color: BiomeColor,
-) -> BakedColorManager {
-    let json: IndexRaw = serde_json::from_reader(index_file).unwrap();
-    let colormap = if let DynamicImage::ImageRgba8(img) =
-        image::load(colormap_file, ImageFormat::Png).unwrap()
+) -> Result<BakedColorManager, String> {
+    let json: IndexRaw =
+        serde_json::from_reader(index_file).map_err(|e| format!("invalid index: {}", e))?;
+    let colormap = if let DynamicImage::ImageRgba8(img) = image::load(colormap_file, ImageFormat::Png)
+        .map_err(|e| format!("invalid image: colormap: {}", e))?
     {
         img
     } else {
-        panic!("invalid image: colormap");
+        return Err(String::from("invalid image: colormap"));
     };
-    let weightmap = if let DynamicImage::ImageLuma8(img) =
-        image::load(weightmap_file, ImageFormat::Png).unwrap()
+    let weightmap = if let DynamicImage::ImageLuma8(img) = image::load(weightmap_file, ImageFormat::Png)
+        .map_err(|e| format!("invalid image: weightmap: {}", e))?
     {
         img
     } else {
-        panic!("invalid image: weightmap");
+        return Err(String::from("invalid image: weightmap"));
     };
-    BakedColorManager::from_raw(json.data, colormap, weightmap, biome_color)
+    Ok(BakedColorManager::from_raw(json.data, colormap, weightmap, biome_color))
+}
+
+use super::overrides::ColorOverride;
+use super::overrides::ColorOverrides;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorRaw {
+    Hex(String),
+    Array([u8; 4]),
+}
+
+fn raw2rgba(raw: ColorRaw) -> Result<Rgba<u8>, String> {
+    match raw {
+        ColorRaw::Array(c) => Ok(Rgba::from(c)),
+        ColorRaw::Hex(s) => {
+            let hex = s.trim_start_matches('#');
+            let c = u32::from_str_radix(hex, 16).map_err(|_e| format!("invalid color: {}", s))?;
+            match hex.len() {
+                6 => Ok(Rgba::from([(c >> 16) as u8, (c >> 8) as u8, c as u8, 0xFF])),
+                8 => Ok(Rgba::from([(c >> 24) as u8, (c >> 16) as u8, (c >> 8) as u8, c as u8])),
+                _ => Err(format!("invalid color: {}", s)),
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ColorOverrideRaw {
+    #[serde(default)]
+    when: Vec<String>,
+    color: ColorRaw,
+    weight: Option<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorOverrideListRaw {
+    One(ColorOverrideRaw),
+    Many(Vec<ColorOverrideRaw>),
+}
+
+pub fn build_color_overrides<R: Read>(override_file: R) -> Result<ColorOverrides, String> {
+    let json: HashMap<String, ColorOverrideListRaw> =
+        serde_json::from_reader(override_file).map_err(|e| e.to_string())?;
+    let mut data = HashMap::with_capacity(json.len());
+    for (block, list) in json {
+        let list = match list {
+            ColorOverrideListRaw::One(one) => vec![one],
+            ColorOverrideListRaw::Many(many) => many,
+        };
+        let mut overrides = Vec::with_capacity(list.len());
+        for raw in list {
+            overrides.push(ColorOverride {
+                when: raw.when,
+                color: raw2rgba(raw.color)?,
+                weight: raw.weight.unwrap_or(255),
+            });
+        }
+        data.insert(block, overrides);
+    }
+    Ok(ColorOverrides::from_raw(data))
+}
+
+use super::biome::InnerColor;
+use super::rules::NamePattern;
+use super::rules::TintRule;
+use super::rules::TintRules;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum InnerColorRaw {
+    None,
+    Water,
+    Grass,
+    Foliage,
+}
+
+#[derive(Deserialize)]
+struct TintRuleRaw {
+    pattern: Option<String>,
+    regex: Option<String>,
+    tint: Option<InnerColorRaw>,
+    color: Option<ColorRaw>,
+}
+
+pub fn build_tint_rules<R: Read>(rules_file: R) -> Result<TintRules, String> {
+    let json: Vec<TintRuleRaw> = serde_json::from_reader(rules_file).map_err(|e| e.to_string())?;
+    let mut rules = Vec::with_capacity(json.len());
+    for raw in json {
+        let pattern = match (raw.pattern, raw.regex) {
+            (Some(glob), None) => NamePattern::Glob(glob),
+            (None, Some(re)) => NamePattern::Regex(regex::Regex::new(re.as_str()).map_err(|e| e.to_string())?),
+            _ => return Err(String::from("exactly one of `pattern` and `regex` is required")),
+        };
+        let tint = match raw.tint {
+            Some(InnerColorRaw::Water) => InnerColor::Water,
+            Some(InnerColorRaw::Grass) => InnerColor::Grass,
+            Some(InnerColorRaw::Foliage) => InnerColor::Foliage,
+            Some(InnerColorRaw::None) | None => InnerColor::None,
+        };
+        let color = match raw.color {
+            Some(c) => Some(raw2rgba(c)?),
+            None => None,
+        };
+        rules.push(TintRule { pattern, tint, color });
+    }
+    Ok(TintRules::from_raw(rules))
 }
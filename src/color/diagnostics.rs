@@ -0,0 +1,111 @@
+use std::collections::btree_map::BTreeMap;
+use std::collections::btree_set::BTreeSet;
+use std::sync::Mutex;
+
+
+/// whether `joined` is the parts of `state` joined by ","
+fn is_joined<'a, I: Iterator<Item = &'a str>>(joined: &str, state: I) -> bool {
+    let mut rest = joined;
+    for (i, part) in state.enumerate() {
+        if i > 0 {
+            rest = match rest.strip_prefix(',') {
+                Some(rest) => rest,
+                None => return false,
+            };
+        }
+        rest = match rest.strip_prefix(part) {
+            Some(rest) => rest,
+            None => return false,
+        };
+    }
+    rest.is_empty()
+}
+
+/**
+ * collect block names and state combinations that can not be found in the color index
+ */
+#[derive(Default)]
+pub struct Diagnostics {
+
+    missing: Mutex<BTreeMap<String, usize>>,
+
+    unmatched: Mutex<BTreeMap<String, BTreeSet<String>>>,
+
+}
+
+impl Diagnostics {
+
+    pub fn record_missing(&self, block: &str) {
+        if let Ok(mut missing) = self.missing.lock() {
+            if let Some(count) = missing.get_mut(block) {
+                *count += 1;
+            } else {
+                missing.insert(String::from(block), 1);
+            }
+        }
+    }
+
+    /// the state is joined only the first time it is recorded
+    pub fn record_unmatched<'a, I: Iterator<Item = &'a str> + Clone>(&self, block: &str, state: I) {
+        if let Ok(mut unmatched) = self.unmatched.lock() {
+            if let Some(states) = unmatched.get_mut(block) {
+                if !states.iter().any(|s| is_joined(s.as_str(), state.clone())) {
+                    states.insert(state.collect::<Vec<_>>().join(","));
+                }
+            } else {
+                let mut states = BTreeSet::new();
+                states.insert(state.collect::<Vec<_>>().join(","));
+                unmatched.insert(String::from(block), states);
+            }
+        }
+    }
+
+    /// missing block names with the times they were looked up
+    pub fn missing_blocks(&self) -> Vec<(String, usize)> {
+        self.missing.lock().map(|m| m.iter().map(|(k, v)| (k.clone(), *v)).collect()).unwrap_or_default()
+    }
+
+    /// block names with every state combination that matched no model
+    pub fn unmatched_states(&self) -> Vec<(String, Vec<String>)> {
+        self.unmatched.lock().map(|m| m.iter().map(|(k, v)| (k.clone(), v.iter().cloned().collect())).collect()).unwrap_or_default()
+    }
+
+    /// whether the block was ever missing or unmatched
+    pub fn contains(&self, block: &str) -> bool {
+        let missing = self.missing.lock().map(|m| m.contains_key(block)).unwrap_or(false);
+        let unmatched = self.unmatched.lock().map(|m| m.contains_key(block)).unwrap_or(false);
+        missing || unmatched
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let missing = self.missing.lock().map(|m| m.is_empty()).unwrap_or(true);
+        let unmatched = self.unmatched.lock().map(|m| m.is_empty()).unwrap_or(true);
+        missing && unmatched
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_diagnostics_collect() {
+        use super::Diagnostics;
+
+        let diag = Diagnostics::default();
+        assert!(diag.is_empty());
+        assert!(!diag.contains("mod:stone"));
+        diag.record_missing("mod:stone");
+        diag.record_missing("mod:stone");
+        diag.record_missing("mod:dirt");
+        diag.record_unmatched("minecraft:oak_stairs", ["facing=up", "half=top"].iter().copied());
+        diag.record_unmatched("minecraft:oak_stairs", ["facing=up", "half=top"].iter().copied());
+        diag.record_unmatched("minecraft:oak_stairs", ["facing=up"].iter().copied());
+        diag.record_unmatched("minecraft:oak_stairs", [].iter().copied());
+        diag.record_unmatched("minecraft:oak_stairs", [].iter().copied());
+        assert!(diag.contains("mod:stone"));
+        assert!(diag.contains("minecraft:oak_stairs"));
+        assert_eq!(diag.missing_blocks(), vec![(String::from("mod:dirt"), 1), (String::from("mod:stone"), 2)]);
+        assert_eq!(diag.unmatched_states(), vec![(String::from("minecraft:oak_stairs"), vec![String::new(), String::from("facing=up"), String::from("facing=up,half=top")])]);
+    }
+
+}
@@ -2,6 +2,9 @@ pub mod blockstate;
 pub mod biome;
 pub mod de;
 pub mod calculate;
+pub mod diagnostics;
+pub mod overrides;
+pub mod rules;
 
 use std::iter::Empty;
 use std::collections::hash_map::HashMap;
@@ -16,13 +19,18 @@ use biome::Biome;
 use biome::BiomeColor;
 use biome::InnerColor;
 use de::BlockStateC;
+use diagnostics::Diagnostics;
+use overrides::ColorOverrides;
+use rules::TintRules;
 
 pub trait ColorManager {
 
-    fn get_basic_color<'a, I: Iterator<Item=&'a str>>(&'a self, block: &'a str, key: I, water_logged: bool) -> Rgba<u8>;
+    fn get_basic_color<'a, I: Iterator<Item=&'a str> + Clone>(&'a self, block: &'a str, key: I, water_logged: bool) -> Rgba<u8>;
     
     fn get_modified_color(&self, basic: Rgba<u8>, inner_color: &InnerColor, height: i32, biome: &Biome, water_logged: bool) -> Rgba<u8>;
 
+    fn get_tint(&self, block: &str) -> InnerColor;
+
 }
 
 
@@ -38,6 +46,12 @@ pub struct BakedColorManager {
 
     water_basic: (Rgba<u8>, u16),
 
+    overrides: ColorOverrides,
+
+    rules: TintRules,
+
+    diagnostics: Diagnostics,
+
 }
 
 impl BakedColorManager {
@@ -49,51 +63,83 @@ impl BakedColorManager {
             weightmap: weightmap.into_raw(),
             biome_color,
             water_basic: (Rgba::from([0, 0, 0, 0]), 0),
+            overrides: ColorOverrides::default(),
+            rules: TintRules::default(),
+            diagnostics: Diagnostics::default(),
         };
         if let Some(blockstate) = obj.index.get("minecraft:water") {
             let it: Empty<&str> = Empty::default();
             if let Some(index) = blockstate.get(it).first() {
                 let water_index = index.clone();
-                obj.water_basic = (obj.colormap[water_index], obj.weightmap[water_index] as u16);
+                if let (Some(color), Some(weight)) = (obj.colormap.get(water_index), obj.weightmap.get(water_index)) {
+                    obj.water_basic = (*color, *weight as u16);
+                }
             }
         }       
         obj
     }
+
+    /// add user colors consulted before the baked index; later overrides take precedence
+    pub fn add_overrides(&mut self, overrides: ColorOverrides) {
+        self.overrides.extend(overrides);
+    }
+
+    /// add tint rules consulted before the current ones
+    pub fn add_tint_rules(&mut self, rules: TintRules) {
+        self.rules.prepend(rules);
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn find_biome(&self, name: &str) -> Option<Biome> {
+        self.biome_color.find(name)
+    }
 }
 
 impl ColorManager for BakedColorManager {
 
-    fn get_basic_color<'a, I: Iterator<Item=&'a str>>(&'a self, block: &'a str, key: I, water_logged: bool) -> Rgba<u8> {
-        if let Some(blockstate) = self.index.get(block) {
-            let colors_index = blockstate.get(key);
-            if colors_index.len() == 0 && !water_logged {
-                return Rgba::from([0, 0, 0, 0]);
-            }
-            let mut colors_tuple: Vec<_> = colors_index.into_iter().map(|i| (self.colormap[i], self.weightmap[i] as u16)).collect();
-            colors_tuple.sort_by_key(|t| t.1);
-            let max_w = if water_logged {
-                255
-            } else {
-                colors_tuple.last().unwrap().1
-            };
-            if max_w == 0 {
-                return Rgba::from([0, 0, 0, 0]);
-            }
-            let mut final_color = Rgba::from([0, 0, 0, 0]);
-            for (mut color, w) in colors_tuple {
-                if w * 4 < max_w {
-                    continue;
+    fn get_basic_color<'a, I: Iterator<Item=&'a str> + Clone>(&'a self, block: &'a str, key: I, water_logged: bool) -> Rgba<u8> {
+        let mut colors_tuple: Vec<_> = if let Some(o) = self.overrides.get(block, key.clone()) {
+            vec![(o.color, o.weight as u16)]
+        } else if let Some(blockstate) = self.index.get(block) {
+            let colors_index = blockstate.get(key.clone());
+            if colors_index.is_empty() {
+                self.diagnostics.record_unmatched(block, key);
+                if let Some(color) = self.rules.fallback(block) {
+                    return color;
+                }
+                if !water_logged {
+                    return Rgba::from([0, 0, 0, 0]);
                 }
-                color[0] = (color[0] as u16 * w / max_w) as u8;
-                color[1] = (color[1] as u16 * w / max_w) as u8;
-                color[2] = (color[2] as u16 * w / max_w) as u8;
-                color[3] = (color[3] as u16 * w / max_w) as u8;
-                final_color.blend(&color)
             }
-            final_color
+            colors_index.into_iter().map(|i| (self.colormap[i], self.weightmap[i] as u16)).collect()
+        } else {
+            self.diagnostics.record_missing(block);
+            return self.rules.fallback(block).unwrap_or_else(|| Rgba::from([0, 0, 0, 0]));
+        };
+        colors_tuple.sort_by_key(|t| t.1);
+        let max_w = if water_logged {
+            255
         } else {
-            Rgba::from([0, 0, 0, 0])
+            colors_tuple.last().unwrap().1
+        };
+        if max_w == 0 {
+            return Rgba::from([0, 0, 0, 0]);
+        }
+        let mut final_color = Rgba::from([0, 0, 0, 0]);
+        for (mut color, w) in colors_tuple {
+            if w * 4 < max_w {
+                continue;
+            }
+            color[0] = (color[0] as u16 * w / max_w) as u8;
+            color[1] = (color[1] as u16 * w / max_w) as u8;
+            color[2] = (color[2] as u16 * w / max_w) as u8;
+            color[3] = (color[3] as u16 * w / max_w) as u8;
+            final_color.blend(&color)
         }
+        final_color
     }
     
     fn get_modified_color(&self, mut basic: Rgba<u8>, inner_color: &InnerColor, height: i32, biome: &Biome, water_logged: bool) -> Rgba<u8> {
@@ -111,6 +157,10 @@ impl ColorManager for BakedColorManager {
         }
     }
 
+    fn get_tint(&self, block: &str) -> InnerColor {
+        self.rules.tint(block)
+    }
+
 }
 
 fn color_mul(mut a: Rgba<u8>, b: Rgb<u8>) -> Rgba<u8> {
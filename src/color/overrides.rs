@@ -0,0 +1,80 @@
+use std::collections::hash_map::HashMap;
+
+use image::Rgba;
+
+
+/**
+ * fixed color for one block, applied when every `when` state is present
+ */
+#[derive(Debug, Clone)]
+pub struct ColorOverride {
+
+    pub when: Vec<String>,
+
+    pub color: Rgba<u8>,
+
+    pub weight: u8,
+
+}
+
+impl ColorOverride {
+
+    pub fn matches<'a, I: Iterator<Item = &'a str> + Clone>(&self, key: I) -> bool {
+        self.when.iter().all(|cond| key.clone().any(|k| k == cond))
+    }
+}
+
+
+#[derive(Default)]
+pub struct ColorOverrides {
+
+    data: HashMap<String, Vec<ColorOverride>>,
+
+}
+
+impl ColorOverrides {
+
+    pub fn from_raw(data: HashMap<String, Vec<ColorOverride>>) -> Self {
+        ColorOverrides {
+            data
+        }
+    }
+
+    /// first override of the block that matches the state
+    pub fn get<'a, I: Iterator<Item = &'a str> + Clone>(&self, block: &str, key: I) -> Option<&ColorOverride> {
+        self.data.get(block)?.iter().find(|o| o.matches(key.clone()))
+    }
+
+    /// merge another set of overrides, whose rules are consulted first
+    pub fn extend(&mut self, other: ColorOverrides) {
+        for (block, mut list) in other.data {
+            if let Some(old) = self.data.remove(&block) {
+                list.extend(old);
+            }
+            self.data.insert(block, list);
+        }
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_override_match() {
+        use std::collections::hash_map::HashMap;
+        use image::Rgba;
+        use super::ColorOverride;
+        use super::ColorOverrides;
+
+        let mut data = HashMap::new();
+        data.insert(String::from("mod:lamp"), vec![
+            ColorOverride { when: vec![String::from("lit=true")], color: Rgba::from([255, 255, 0, 255]), weight: 255 },
+            ColorOverride { when: Vec::new(), color: Rgba::from([64, 64, 0, 255]), weight: 255 },
+        ]);
+        let overrides = ColorOverrides::from_raw(data);
+        assert_eq!(overrides.get("mod:lamp", ["facing=up", "lit=true"].iter().copied()).unwrap().color, Rgba::from([255, 255, 0, 255]));
+        assert_eq!(overrides.get("mod:lamp", ["lit=false"].iter().copied()).unwrap().color, Rgba::from([64, 64, 0, 255]));
+        assert!(overrides.get("mod:stone", [].iter().copied()).is_none());
+    }
+
+}
@@ -0,0 +1,168 @@
+use image::Rgba;
+use regex::Regex;
+
+use super::biome::InnerColor;
+
+
+/**
+ * block name pattern: glob with `*` and `?`, or regular expression
+ */
+#[derive(Debug)]
+pub enum NamePattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl NamePattern {
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob_match(glob.as_bytes(), name.as_bytes()),
+            Self::Regex(re) => re.is_match(name),
+        }
+    }
+}
+
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let mut p = 0;
+    let mut n = 0;
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((sp, sn)) = star {
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+
+#[derive(Debug)]
+pub struct TintRule {
+
+    pub pattern: NamePattern,
+
+    pub tint: InnerColor,
+
+    pub color: Option<Rgba<u8>>,
+
+}
+
+
+/**
+ * map block names to biome tint class and fallback color; the first matching rule wins
+ */
+#[derive(Debug)]
+pub struct TintRules {
+
+    rules: Vec<TintRule>,
+
+}
+
+impl Default for TintRules {
+
+    fn default() -> Self {
+        let builtin = [
+            ("minecraft:water", InnerColor::Water),
+            ("minecraft:grass_block", InnerColor::Grass),
+            ("minecraft:lily_pad", InnerColor::Grass),
+            ("*leaves", InnerColor::Foliage),
+        ];
+        TintRules {
+            rules: builtin.iter().map(|(pattern, tint)| TintRule {
+                pattern: NamePattern::Glob(String::from(*pattern)),
+                tint: *tint,
+                color: None
+            }).collect()
+        }
+    }
+}
+
+impl TintRules {
+
+    pub fn from_raw(rules: Vec<TintRule>) -> Self {
+        TintRules {
+            rules
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&TintRule> {
+        self.rules.iter().find(|rule| rule.pattern.matches(name))
+    }
+
+    pub fn tint(&self, name: &str) -> InnerColor {
+        self.find(name).map(|rule| rule.tint).unwrap_or(InnerColor::None)
+    }
+
+    /// fallback color of the first matching rule that has one
+    pub fn fallback(&self, name: &str) -> Option<Rgba<u8>> {
+        self.rules.iter().filter(|rule| rule.color.is_some()).find(|rule| rule.pattern.matches(name))?.color
+    }
+
+    /// put rules of `other` in front of the current ones
+    pub fn prepend(&mut self, mut other: TintRules) {
+        other.rules.append(&mut self.rules);
+        self.rules = other.rules;
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_glob_match() {
+        use super::glob_match;
+
+        assert!(glob_match(b"*leaves", b"minecraft:oak_leaves"));
+        assert!(glob_match(b"biomesoplenty:*_leaves", b"biomesoplenty:fir_leaves"));
+        assert!(!glob_match(b"biomesoplenty:*_leaves", b"minecraft:fir_leaves"));
+        assert!(glob_match(b"mod:?ake", b"mod:lake"));
+        assert!(!glob_match(b"mod:?ake", b"mod:flake"));
+        assert!(glob_match(b"*:*grass*", b"byg:meadow_grass_block"));
+        assert!(glob_match(b"minecraft:water", b"minecraft:water"));
+        assert!(!glob_match(b"minecraft:water", b"minecraft:water_cauldron"));
+    }
+
+    #[test]
+    fn test_tint_rules() {
+        use image::Rgba;
+        use regex::Regex;
+        use super::NamePattern;
+        use super::TintRule;
+        use super::TintRules;
+        use crate::color::biome::InnerColor;
+
+        let mut rules = TintRules::default();
+        assert_eq!(rules.tint("minecraft:grass_block"), InnerColor::Grass);
+        assert_eq!(rules.tint("minecraft:birch_leaves"), InnerColor::Foliage);
+        assert_eq!(rules.tint("byg:lush_grass_block"), InnerColor::None);
+
+        rules.prepend(TintRules::from_raw(vec![
+            TintRule {
+                pattern: NamePattern::Regex(Regex::new("^byg:.*grass_block$").unwrap()),
+                tint: InnerColor::Grass,
+                color: Some(Rgba::from([127, 178, 56, 255]))
+            },
+            TintRule {
+                pattern: NamePattern::Glob(String::from("*:cherry_leaves")),
+                tint: InnerColor::None,
+                color: None
+            },
+        ]));
+        assert_eq!(rules.tint("byg:lush_grass_block"), InnerColor::Grass);
+        assert_eq!(rules.fallback("byg:lush_grass_block"), Some(Rgba::from([127, 178, 56, 255])));
+        assert_eq!(rules.tint("mod:cherry_leaves"), InnerColor::None);
+        assert_eq!(rules.tint("mod:fir_leaves"), InnerColor::Foliage);
+        assert_eq!(rules.fallback("mod:fir_leaves"), None);
+    }
+
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use std::collections::HashMap;
+
+use zip::ZipArchive;
+use image::RgbaImage;
+use serde_json::Value;
+
+
+/**
+ * resource packs and version jars; files in later packs replace the ones in earlier packs
+ */
+pub struct AssetsLoader {
+
+    archives: Vec<ZipArchive<File>>,
+
+    names: HashMap<String, usize>,
+
+}
+
+/// split `namespace:path`, the namespace is `minecraft` if omitted
+pub fn split_location(location: &str) -> (&str, &str) {
+    if let Some(i) = location.find(':') {
+        (&location[..i], &location[i + 1..])
+    } else {
+        ("minecraft", location)
+    }
+}
+
+impl AssetsLoader {
+
+    pub fn open<P: AsRef<Path>>(resources: &[P]) -> io::Result<Self> {
+        let mut archives = Vec::with_capacity(resources.len());
+        let mut names = HashMap::new();
+        for (i, resource) in resources.iter().enumerate() {
+            let archive = ZipArchive::new(File::open(resource)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            for name in archive.file_names() {
+                names.insert(String::from(name), i);
+            }
+            archives.push(archive);
+        }
+        Ok(AssetsLoader {
+            archives,
+            names,
+        })
+    }
+
+    /// all `(namespace, name)` with a blockstate file, sorted
+    pub fn get_blocks(&self) -> Vec<(String, String)> {
+        const PREFIX: &str = "assets/";
+        const MIDDLE: &str = "/blockstates/";
+        const EXT: &str = ".json";
+        let mut blocks: Vec<_> = self.names.keys().filter_map(|name| {
+            if !name.starts_with(PREFIX) || !name.ends_with(EXT) {
+                return None;
+            }
+            let rest = &name[PREFIX.len() .. name.len() - EXT.len()];
+            let i = rest.find(MIDDLE)?;
+            let namespace = &rest[..i];
+            if namespace.contains('/') {
+                return None;
+            }
+            Some((String::from(namespace), String::from(&rest[i + MIDDLE.len()..])))
+        }).collect();
+        blocks.sort();
+        blocks
+    }
+
+    fn read(&mut self, full: &str) -> Option<Vec<u8>> {
+        let i = *self.names.get(full)?;
+        let mut file = self.archives[i].by_name(full).ok()?;
+        let mut buf = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buf).ok()?;
+        Some(buf)
+    }
+
+    fn read_json(&mut self, full: &str) -> Option<Value> {
+        let buf = self.read(full)?;
+        match serde_json::from_slice(buf.as_slice()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::warn!("invalid json {}: {}", full, e);
+                None
+            }
+        }
+    }
+
+    pub fn get_blockstate(&mut self, namespace: &str, name: &str) -> Option<Value> {
+        self.read_json(format!("assets/{}/blockstates/{}.json", namespace, name).as_str())
+    }
+
+    pub fn get_model(&mut self, namespace: &str, name: &str) -> Option<Value> {
+        self.read_json(format!("assets/{}/models/{}.json", namespace, name).as_str())
+    }
+
+    /// texture and whether it has alpha channel; animated textures are cut to the first frame
+    pub fn get_texture(&mut self, namespace: &str, name: &str) -> Option<(RgbaImage, bool)> {
+        let full = format!("assets/{}/textures/{}.png", namespace, name);
+        let info = format!("{}.mcmeta", full);
+        let buf = self.read(full.as_str())?;
+        let img = match image::load_from_memory_with_format(buf.as_slice(), image::ImageFormat::Png) {
+            Ok(img) => img,
+            Err(e) => {
+                log::warn!("invalid texture {}: {}", full, e);
+                return None;
+            }
+        };
+        let alpha = img.color().has_alpha();
+        let mut img = img.to_rgba8();
+        if self.names.contains_key(&info) && img.height() > img.width() {
+            let w = img.width();
+            img = image::imageops::crop(&mut img, 0, 0, w, w).to_image();
+        }
+        Some((img, alpha))
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use image::GrayImage;
+use image::Luma;
+use image::Rgba;
+use image::RgbaImage;
+use image::imageops;
+use image::imageops::FilterType;
+
+use super::assets::split_location;
+use super::assets::AssetsLoader;
+use super::model::Face;
+
+
+pub const CELL: u32 = 16;
+
+const EQUIV_ROTATE1: [i32; 4] = [0, 270, 90, 180];
+const EQUIV_ROTATE2: [i32; 4] = [90, 180, 0, 270];
+
+
+pub struct TextureCache<'a> {
+    loader: &'a mut AssetsLoader,
+    cache: HashMap<String, Option<(RgbaImage, bool)>>,
+}
+
+impl<'a> TextureCache<'a> {
+
+    pub fn new(loader: &'a mut AssetsLoader) -> Self {
+        TextureCache {
+            loader,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, name: &str) -> Option<&(RgbaImage, bool)> {
+        let (namespace, path) = split_location(name);
+        let loader = &mut self.loader;
+        self.cache.entry(format!("{}:{}", namespace, path))
+            .or_insert_with(|| loader.get_texture(namespace, path))
+            .as_ref()
+    }
+}
+
+
+/// the face projected on the top view: `(area, uv, rotation, height)`
+fn rectify(vertexs: &[[f32; 3]; 4], face: &Face) -> ([f32; 4], [f32; 4], i32, f32) {
+    let h = vertexs[0][1];
+    let mut vmin = vertexs[0];
+    let mut imin = 0;
+    let mut vmax = vertexs[0];
+    for (c, v) in vertexs.iter().enumerate().skip(1) {
+        if v[0] <= vmin[0] && v[2] <= vmin[2] {
+            imin = c;
+            vmin = *v;
+        }
+        if v[0] >= vmax[0] && v[2] >= vmax[2] {
+            vmax = *v;
+        }
+    }
+    let src_uv = [vmin[0], vmin[2], vmax[0], vmax[2]];
+    let mut uv = face.uv;
+    let mut k = 0;
+    if uv[0] > uv[2] {
+        k |= 0x1;
+        uv.swap(0, 2);
+    }
+    if uv[1] > uv[3] {
+        k |= 0x2;
+        uv.swap(1, 3);
+    }
+    let r = (EQUIV_ROTATE2[imin] + EQUIV_ROTATE1[k] + face.rotation).rem_euclid(360);
+    (src_uv, uv, r, h)
+}
+
+fn blend(bg: &mut Rgba<u8>, fg: &Rgba<u8>) {
+    let fa = fg[3] as f32 / 255.0;
+    let ba = bg[3] as f32 / 255.0;
+    let a = fa + ba * (1.0 - fa);
+    for c in 0..3 {
+        let v = if a > 0.0 {
+            (fg[c] as f32 / 255.0 * fa + bg[c] as f32 / 255.0 * ba * (1.0 - fa)) / a
+        } else {
+            0.0
+        };
+        bg[c] = (v * 255.0) as u8;
+    }
+    bg[3] = (a * 255.0) as u8;
+}
+
+
+/**
+ * draw the top view of every model in a 16x16 cell, cells are placed row by row
+ */
+pub struct Baker {
+    iw: u32,
+    ih: u32,
+    img: RgbaImage,
+    heightmap: GrayImage,
+}
+
+impl Baker {
+
+    pub fn new(count: usize, linewidth: u32) -> Self {
+        let iw = linewidth;
+        let ih = (count as u32 + linewidth - 1) / linewidth;
+        Baker {
+            iw,
+            ih,
+            img: RgbaImage::new(iw * CELL, ih * CELL),
+            heightmap: GrayImage::new(iw, ih),
+        }
+    }
+
+    pub fn draw(&mut self, faces: &[([[f32; 3]; 4], Face)], index: usize, textures: &mut TextureCache) -> bool {
+        if index as u32 >= self.ih * self.iw || faces.is_empty() {
+            return false;
+        }
+        let ix = index as u32 % self.iw;
+        let iy = index as u32 / self.iw;
+        let mut height = 0.0;
+        for (vertexs, face) in faces {
+            let (src_uv, tex_uv, r, h) = rectify(vertexs, face);
+            let (tex, alpha) = match textures.get(face.texture.as_str()) {
+                Some(t) => t,
+                None => continue,
+            };
+            let tw = tex.width() as f32;
+            let th = tex.height() as f32;
+            let tx0 = (tex_uv[0] / 16.0 * tw).floor().max(0.0) as u32;
+            let ty0 = (tex_uv[1] / 16.0 * th).floor().max(0.0) as u32;
+            let tx1 = ((tex_uv[2] / 16.0 * tw).ceil() as u32).min(tex.width());
+            let ty1 = ((tex_uv[3] / 16.0 * th).ceil() as u32).min(tex.height());
+            let sx0 = src_uv[0].floor().max(0.0) as u32;
+            let sy0 = src_uv[1].floor().max(0.0) as u32;
+            let sx1 = (src_uv[2].ceil().max(0.0) as u32).min(CELL);
+            let sy1 = (src_uv[3].ceil().max(0.0) as u32).min(CELL);
+            if tx1 <= tx0 || ty1 <= ty0 || sx1 <= sx0 || sy1 <= sy0 {
+                continue;
+            }
+            let part = imageops::crop_imm(tex, tx0, ty0, tx1 - tx0, ty1 - ty0).to_image();
+            let part = match r / 90 {
+                1 => imageops::rotate90(&part),
+                2 => imageops::rotate180(&part),
+                3 => imageops::rotate270(&part),
+                _ => part,
+            };
+            let part = imageops::resize(&part, sx1 - sx0, sy1 - sy0, FilterType::Nearest);
+            for (x, y, p) in part.enumerate_pixels() {
+                let target = self.img.get_pixel_mut(ix * CELL + sx0 + x, iy * CELL + sy0 + y);
+                if *alpha {
+                    blend(target, p);
+                } else {
+                    *target = Rgba::from([p[0], p[1], p[2], 255]);
+                }
+            }
+            height = h;
+        }
+        self.heightmap.put_pixel(ix, iy, Luma::from([(height * 8.0) as u8]));
+        true
+    }
+
+    pub fn baked(&self) -> &RgbaImage {
+        &self.img
+    }
+
+    pub fn heightmap(&self) -> &GrayImage {
+        &self.heightmap
+    }
+
+    /// average color of the opaque pixels in each cell, weighted by their count
+    pub fn color_extraction(&self) -> (RgbaImage, GrayImage) {
+        let mut colormap = RgbaImage::new(self.iw, self.ih);
+        let mut weightmap = GrayImage::new(self.iw, self.ih);
+        for x in 0..self.iw {
+            for y in 0..self.ih {
+                let mut sum = [0u32; 4];
+                let mut count = 0;
+                for dx in 0..CELL {
+                    for dy in 0..CELL {
+                        let p = self.img.get_pixel(x * CELL + dx, y * CELL + dy);
+                        if p[3] > 0 {
+                            for (s, c) in sum.iter_mut().zip(p.0.iter()) {
+                                *s += *c as u32;
+                            }
+                            count += 1;
+                        }
+                    }
+                }
+                colormap.put_pixel(x, y, Rgba::from(sum.map(|s| s.checked_div(count).unwrap_or(0) as u8)));
+                weightmap.put_pixel(x, y, Luma::from([std::cmp::max(count as i32 - 1, 0) as u8]));
+            }
+        }
+        (colormap, weightmap)
+    }
+}
@@ -0,0 +1,224 @@
+use std::io;
+use std::io::Write;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+
+pub const VERSIONS: [&str; 4] = ["1.13", "1.14", "1.15", "1.16"];
+
+const DEFAULT_WATER: u32 = 0x3F76E4;
+
+/// `(id, name, temperature, rainfall)`
+type BiomeDef = (usize, &'static str, f32, f32);
+
+const BIOMES_1_13: &[BiomeDef] = &[
+    (  0, "minecraft:ocean",                            0.5,  0.5),
+    (  1, "minecraft:plains",                           0.8,  0.4),
+    (  2, "minecraft:desert",                           2.0,  0.0),
+    (  3, "minecraft:mountains",                        0.2,  0.3),
+    (  4, "minecraft:forest",                           0.7,  0.8),
+    (  5, "minecraft:taiga",                            0.25, 0.8),
+    (  6, "minecraft:swamp",                            0.8,  0.9),
+    (  7, "minecraft:river",                            0.5,  0.5),
+    (  8, "minecraft:nether",                           2.0,  0.0),
+    (  9, "minecraft:the_end",                          0.5,  0.5),
+    ( 10, "minecraft:frozen_ocean",                     0.0,  0.5),
+    ( 11, "minecraft:frozen_river",                     0.0,  0.5),
+    ( 12, "minecraft:snowy_tundra",                     0.0,  0.5),
+    ( 13, "minecraft:snowy_mountains",                  0.0,  0.5),
+    ( 14, "minecraft:mushroom_fields",                  0.9,  1.0),
+    ( 15, "minecraft:mushroom_field_shore",             0.9,  1.0),
+    ( 16, "minecraft:beach",                            0.8,  0.4),
+    ( 17, "minecraft:desert_hills",                     2.0,  0.0),
+    ( 18, "minecraft:wooded_hills",                     0.7,  0.8),
+    ( 19, "minecraft:taiga_hills",                      0.25, 0.8),
+    ( 20, "minecraft:mountain_edge",                    0.2,  0.3),
+    ( 21, "minecraft:jungle",                           0.95, 0.9),
+    ( 22, "minecraft:jungle_hills",                     0.95, 0.9),
+    ( 23, "minecraft:jungle_edge",                      0.95, 0.8),
+    ( 24, "minecraft:deep_ocean",                       0.5,  0.5),
+    ( 25, "minecraft:stone_shore",                      0.2,  0.3),
+    ( 26, "minecraft:snowy_beach",                      0.05, 0.3),
+    ( 27, "minecraft:birch_forest",                     0.6,  0.6),
+    ( 28, "minecraft:birch_forest_hills",               0.6,  0.6),
+    ( 29, "minecraft:dark_forest",                      0.7,  0.8),
+    ( 30, "minecraft:snowy_taiga",                     -0.5,  0.4),
+    ( 31, "minecraft:snowy_taiga_hills",               -0.5,  0.4),
+    ( 32, "minecraft:giant_tree_taiga",                 0.3,  0.8),
+    ( 33, "minecraft:giant_tree_taiga_hills",           0.3,  0.8),
+    ( 34, "minecraft:wooded_mountains",                 0.2,  0.3),
+    ( 35, "minecraft:savanna",                          1.2,  0.0),
+    ( 36, "minecraft:savanna_plateau",                  1.0,  0.0),
+    ( 37, "minecraft:badlands",                         2.0,  0.0),
+    ( 38, "minecraft:wooded_badlands_plateau",          2.0,  0.0),
+    ( 39, "minecraft:badlands_plateau",                 2.0,  0.0),
+    ( 40, "minecraft:small_end_islands",                0.5,  0.5),
+    ( 41, "minecraft:end_midlands",                     0.5,  0.5),
+    ( 42, "minecraft:end_highlands",                    0.5,  0.5),
+    ( 43, "minecraft:end_barrens",                      0.5,  0.5),
+    ( 44, "minecraft:warm_ocean",                       0.8,  0.5),
+    ( 45, "minecraft:lukewarm_ocean",                   0.8,  0.5),
+    ( 46, "minecraft:cold_ocean",                       0.8,  0.5),
+    ( 47, "minecraft:deep_warm_ocean",                  0.8,  0.5),
+    ( 48, "minecraft:deep_lukewarm_ocean",              0.8,  0.5),
+    ( 49, "minecraft:deep_cold_ocean",                  0.8,  0.5),
+    ( 50, "minecraft:deep_frozen_ocean",                0.8,  0.5),
+    (127, "minecraft:the_void",                         0.5,  0.5),
+    (129, "minecraft:sunflower_plains",                 0.8,  0.4),
+    (130, "minecraft:desert_lakes",                     2.0,  0.0),
+    (131, "minecraft:gravelly_mountains",               0.2,  0.3),
+    (132, "minecraft:flower_forest",                    0.7,  0.8),
+    (133, "minecraft:taiga_mountains",                  0.25, 0.8),
+    (134, "minecraft:swamp_hills",                      0.8,  0.9),
+    (140, "minecraft:ice_spikes",                       0.0,  0.5),
+    (149, "minecraft:modified_jungle",                  0.95, 0.9),
+    (151, "minecraft:modified_jungle_edge",             0.95, 0.8),
+    (155, "minecraft:tall_birch_forest",                0.6,  0.6),
+    (156, "minecraft:tall_birch_hills",                 0.6,  0.6),
+    (157, "minecraft:dark_forest_hills",                0.7,  0.8),
+    (158, "minecraft:snowy_taiga_mountains",           -0.5,  0.4),
+    (160, "minecraft:giant_spruce_taiga",               0.25, 0.8),
+    (161, "minecraft:giant_spruce_taiga_hills",         0.25, 0.8),
+    (162, "minecraft:modified_gravelly_mountains",      0.2,  0.3),
+    (163, "minecraft:shattered_savanna",                1.1,  0.0),
+    (164, "minecraft:shattered_savanna_plateau",        1.0,  0.0),
+    (165, "minecraft:eroded_badlands",                  2.0,  0.0),
+    (166, "minecraft:modified_wooded_badlands_plateau", 2.0,  0.0),
+    (167, "minecraft:modified_badlands_plateau",        2.0,  0.0),
+];
+
+const BIOMES_1_14: &[BiomeDef] = &[
+    (168, "minecraft:bamboo_jungle",                    0.95, 0.9),
+    (169, "minecraft:bamboo_jungle_hills",              0.95, 0.9),
+];
+
+const BIOMES_1_15: &[BiomeDef] = &[];
+
+const BIOMES_1_16: &[BiomeDef] = &[
+    (  8, "minecraft:nether_wastes",                    2.0,  0.0),
+    (170, "minecraft:soul_sand_valley",                 2.0,  0.0),
+    (171, "minecraft:crimson_forest",                   2.0,  0.0),
+    (172, "minecraft:warped_forest",                    2.0,  0.0),
+    (173, "minecraft:basalt_deltas",                    2.0,  0.0),
+];
+
+/// changes of each version, applied in order
+const PATCHES: [&[BiomeDef]; 4] = [BIOMES_1_13, BIOMES_1_14, BIOMES_1_15, BIOMES_1_16];
+
+const WATER: &[(&str, u32)] = &[
+    ("minecraft:swamp",                 0x617B64),
+    ("minecraft:swamp_hills",           0x617B64),
+    ("minecraft:warm_ocean",            0x43D5EE),
+    ("minecraft:deep_warm_ocean",       0x43D5EE),
+    ("minecraft:lukewarm_ocean",        0x45ADF2),
+    ("minecraft:deep_lukewarm_ocean",   0x45ADF2),
+    ("minecraft:cold_ocean",            0x3D57D6),
+    ("minecraft:deep_cold_ocean",       0x3D57D6),
+    ("minecraft:snowy_beach",           0x3D57D6),
+    ("minecraft:snowy_taiga",           0x3D57D6),
+    ("minecraft:snowy_taiga_hills",     0x3D57D6),
+    ("minecraft:snowy_taiga_mountains", 0x3D57D6),
+    ("minecraft:frozen_ocean",          0x3938C9),
+    ("minecraft:deep_frozen_ocean",     0x3938C9),
+    ("minecraft:frozen_river",          0x3938C9),
+];
+
+
+#[derive(Serialize, Clone, Copy)]
+pub enum BiomeColorOps {
+    Fixed(u32),
+    Average(u32),
+}
+
+/// `(grass, foliage)` of the biomes that do not use the colormap directly
+fn special_ops(name: &str) -> Option<(BiomeColorOps, BiomeColorOps)> {
+    match name {
+        "minecraft:swamp" | "minecraft:swamp_hills" => {
+            Some((BiomeColorOps::Fixed(0x4C763C), BiomeColorOps::Fixed(0x4C763C)))
+        }
+        "minecraft:dark_forest" | "minecraft:dark_forest_hills" => {
+            Some((BiomeColorOps::Average(0x28340A), BiomeColorOps::Average(0x28340A)))
+        }
+        "minecraft:badlands"
+        | "minecraft:wooded_badlands_plateau"
+        | "minecraft:badlands_plateau"
+        | "minecraft:eroded_badlands"
+        | "minecraft:modified_wooded_badlands_plateau"
+        | "minecraft:modified_badlands_plateau" => {
+            Some((BiomeColorOps::Fixed(0x90814D), BiomeColorOps::Fixed(0x9E814D)))
+        }
+        _ => None,
+    }
+}
+
+
+/**
+ * one item in `biome.json`
+ */
+#[derive(Serialize)]
+pub struct BiomeOut {
+    pub id: usize,
+    pub name: &'static str,
+    pub temperature: f32,
+    pub rainfall: f32,
+    pub watercolor: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ops_grass: Option<BiomeColorOps>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ops_foliage: Option<BiomeColorOps>,
+}
+
+/// biomes of the version sorted by id, `None` if the version is unknown
+pub fn biome_table(version: &str) -> Option<Vec<BiomeOut>> {
+    let last = VERSIONS.iter().position(|v| *v == version)?;
+    let mut table = BTreeMap::new();
+    for patch in PATCHES.iter().take(last + 1) {
+        for def in patch.iter() {
+            table.insert(def.0, def);
+        }
+    }
+    let biomes = table.into_iter().map(|(id, (_, name, temperature, rainfall))| {
+        let watercolor = WATER.iter().find(|(n, _)| n == name).map(|(_, c)| *c).unwrap_or(DEFAULT_WATER);
+        let ops = special_ops(name);
+        BiomeOut {
+            id,
+            name,
+            temperature: *temperature,
+            rainfall: *rainfall,
+            watercolor,
+            ops_grass: ops.map(|o| o.0),
+            ops_foliage: ops.map(|o| o.1),
+        }
+    }).collect();
+    Some(biomes)
+}
+
+pub fn write_biomes<W: Write>(version: &str, writer: W) -> io::Result<usize> {
+    let biomes = biome_table(version)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown version: {}", version)))?;
+    serde_json::to_writer(writer, &biomes).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(biomes.len())
+}
+
+
+mod test {
+
+    #[test]
+    fn test_biome_table() {
+        use super::biome_table;
+
+        assert!(biome_table("1.12").is_none());
+        let t13 = biome_table("1.13").unwrap();
+        assert!(t13.iter().all(|b| b.id <= 167));
+        assert_eq!(t13.iter().find(|b| b.id == 8).unwrap().name, "minecraft:nether");
+        let t16 = biome_table("1.16").unwrap();
+        assert_eq!(t16.len(), t13.len() + 2 + 4);
+        assert_eq!(t16.iter().find(|b| b.id == 8).unwrap().name, "minecraft:nether_wastes");
+        let swamp = t16.iter().find(|b| b.name == "minecraft:swamp").unwrap();
+        assert_eq!(swamp.watercolor, 0x617B64);
+        assert!(swamp.ops_grass.is_some());
+        assert!(t16.windows(2).all(|w| w[0].id < w[1].id));
+    }
+
+}
@@ -0,0 +1,193 @@
+pub mod assets;
+pub mod state;
+pub mod model;
+pub mod bake;
+pub mod biomes;
+
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use serde::Serialize;
+use image::ImageFormat;
+use image::buffer::ConvertBuffer;
+use image::RgbImage;
+
+use crate::color::de::BlockStateC;
+use assets::split_location;
+use assets::AssetsLoader;
+use state::IdGen;
+use model::Direction;
+use model::Model;
+use model::ModelDef;
+use bake::Baker;
+use bake::TextureCache;
+
+
+pub struct ColorGenOptions {
+    assets: Vec<PathBuf>,
+    output_folder: PathBuf,
+    linewidth: u32,
+}
+
+impl ColorGenOptions {
+
+    pub fn new(assets: Vec<PathBuf>, output_folder: PathBuf) -> Self {
+        ColorGenOptions {
+            assets,
+            output_folder,
+            linewidth: 32,
+        }
+    }
+
+    pub fn set_linewidth(&mut self, linewidth: u32) {
+        if linewidth > 0 {
+            self.linewidth = linewidth;
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+struct IndexConfig {
+    linewidth: u32,
+}
+
+#[derive(Serialize)]
+struct IndexOut<'a> {
+    data: &'a BTreeMap<String, BlockStateC>,
+    config: IndexConfig,
+}
+
+
+/**
+ * build `index.json`, `colormap.png`, `weightmap.png`, `grass.png` and `foliage.png` from resource packs
+ */
+pub struct ColorMapGenerator {
+    options: ColorGenOptions,
+}
+
+impl ColorMapGenerator {
+
+    pub fn new(options: ColorGenOptions) -> Self {
+        ColorMapGenerator {
+            options,
+        }
+    }
+
+    fn load_model(loader: &mut AssetsLoader, defs: &mut HashMap<String, Option<ModelDef>>, name: &str) -> Option<Model> {
+        let mut chain = Vec::new();
+        let mut next = Some(String::from(name));
+        while let Some(current) = next.take() {
+            let (namespace, path) = split_location(current.as_str());
+            let key = format!("{}:{}", namespace, path);
+            if chain.contains(&key) {
+                log::warn!("model parent loop: {}", key);
+                break;
+            }
+            if !defs.contains_key(&key) {
+                let def = loader.get_model(namespace, path).and_then(|json| {
+                    ModelDef::from_json(json).map_err(|e| log::warn!("invalid model {}: {}", key, e)).ok()
+                });
+                defs.insert(key.clone(), def);
+            }
+            match defs.get(&key)? {
+                Some(def) => next = def.parent.clone(),
+                None if chain.is_empty() => return None,
+                None => break,
+            }
+            chain.push(key);
+        }
+        let chain: Vec<&ModelDef> = chain.iter().filter_map(|key| defs.get(key)?.as_ref()).collect();
+        Some(Model::link(chain.as_slice()))
+    }
+
+    pub fn generate(&self) -> io::Result<()> {
+        let odir = &self.options.output_folder;
+        fs::create_dir_all(odir)?;
+        let mut loader = AssetsLoader::open(self.options.assets.as_slice())?;
+
+        log::info!("> generate index");
+        let mut idgen = IdGen::default();
+        let mut data = BTreeMap::new();
+        for (namespace, block) in loader.get_blocks() {
+            let name = format!("{}:{}", namespace, block);
+            match loader.get_blockstate(namespace.as_str(), block.as_str()).and_then(|json| state::parse_blockstate(&json, &mut idgen)) {
+                Some(bs) => {
+                    log::info!("load blockstate   {}", name);
+                    data.insert(name, bs);
+                }
+                None => log::warn!("invalid blockstate   {}", name),
+            }
+        }
+        let index = IndexOut {
+            data: &data,
+            config: IndexConfig { linewidth: self.options.linewidth },
+        };
+        serde_json::to_writer(BufWriter::new(File::create(odir.join("index.json"))?), &index)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        log::info!("> write index");
+
+        log::info!("> generate model");
+        let mut defs = HashMap::new();
+        let mut models: HashMap<String, Option<Model>> = HashMap::new();
+        let count = idgen.total();
+        for id in 1..count {
+            for applied_model in idgen.get(id).unwrap() {
+                if let Entry::Vacant(entry) = models.entry(applied_model.model.clone()) {
+                    let model = Self::load_model(&mut loader, &mut defs, entry.key().as_str());
+                    if model.is_none() {
+                        log::warn!("model is None: {}", entry.key());
+                    }
+                    entry.insert(model);
+                }
+            }
+        }
+
+        log::info!("> render");
+        let mut baker = Baker::new(count, self.options.linewidth);
+        let mut textures = TextureCache::new(&mut loader);
+        for id in 1..count {
+            let parts = idgen.get(id).unwrap();
+            let mut faces = Vec::new();
+            let mut loaded = false;
+            for applied_model in parts {
+                if let Some(Some(model)) = models.get(&applied_model.model) {
+                    faces.extend(applied_model.get_faces(model, Direction::Up));
+                    loaded = true;
+                }
+            }
+            if !loaded {
+                continue;
+            }
+            let name = parts.iter().map(|m| m.model.as_str()).collect::<Vec<_>>().join(" + ");
+            if baker.draw(faces.as_slice(), id, &mut textures) {
+                log::info!("draw [{}] {}", id, name);
+            } else {
+                log::warn!("empty [{}] {}", id, name);
+            }
+        }
+
+        let save_error = |e: image::ImageError| io::Error::new(io::ErrorKind::Other, e.to_string());
+        baker.baked().save_with_format(odir.join("baked.png"), ImageFormat::Png).map_err(save_error)?;
+        baker.heightmap().save_with_format(odir.join("heightmap.png"), ImageFormat::Png).map_err(save_error)?;
+        let (colormap, weightmap) = baker.color_extraction();
+        colormap.save_with_format(odir.join("colormap.png"), ImageFormat::Png).map_err(save_error)?;
+        weightmap.save_with_format(odir.join("weightmap.png"), ImageFormat::Png).map_err(save_error)?;
+        for name in &["grass", "foliage"] {
+            match textures.get(format!("minecraft:colormap/{}", name).as_str()) {
+                Some((tex, _)) => {
+                    let tex: RgbImage = tex.convert();
+                    tex.save_with_format(odir.join(format!("{}.png", name)), ImageFormat::Png).map_err(save_error)?;
+                }
+                None => log::warn!("missing texture: colormap/{}", name),
+            }
+        }
+        Ok(())
+    }
+}
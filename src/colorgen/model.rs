@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::state::AppliedModel;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    West,
+    Down,
+    North,
+    South,
+    Up,
+    East,
+}
+
+use Direction::*;
+
+impl Direction {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "west" => Some(West),
+            "down" | "bottom" => Some(Down),
+            "north" => Some(North),
+            "south" => Some(South),
+            "up" | "top" => Some(Up),
+            "east" => Some(East),
+            _ => None
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn axis(self) -> [f32; 3] {
+        match self {
+            West => [-1.0, 0.0, 0.0],
+            Down => [0.0, -1.0, 0.0],
+            North => [0.0, 0.0, -1.0],
+            South => [0.0, 0.0, 1.0],
+            Up => [0.0, 1.0, 0.0],
+            East => [1.0, 0.0, 0.0],
+        }
+    }
+
+    /// vertex indices of the face; bit 0, 1, 2 of an index select `to` for x, y, z
+    fn vertex_index(self) -> [usize; 4] {
+        match self {
+            West => [0, 4, 2, 7],
+            Down => [0, 1, 4, 5],
+            North => [1, 0, 3, 2],
+            South => [4, 5, 6, 7],
+            Up => [6, 7, 2, 3],
+            East => [5, 1, 7, 3],
+        }
+    }
+
+    fn rotate_factor(self) -> (i32, i32) {
+        match self {
+            West => (90, 0),
+            Down => (0, -90),
+            Up => (0, 90),
+            East => (-90, 0),
+            _ => (0, 0),
+        }
+    }
+
+    /// direction after rotating `x` times around axis x then `y` times around axis y, 90 degrees each
+    pub fn rotate(self, x: usize, y: usize) -> Self {
+        ROTATE[self.index()][x & 0x3][y & 0x3]
+    }
+
+    /// direction that becomes `self` after the rotation
+    pub fn rotate_inv(self, x: usize, y: usize) -> Self {
+        for d in [West, Down, North, South, Up, East].iter() {
+            if d.rotate(x, y) == self {
+                return *d;
+            }
+        }
+        self
+    }
+}
+
+const ROTATE: [[[Direction; 4]; 4]; 6] = [
+    [ // west
+        [West, North, East, South],
+        [West, North, East, South],
+        [West, North, East, South],
+        [West, North, East, South],
+    ],
+    [ // down
+        [Down, Down, Down, Down],
+        [North, East, South, West],
+        [Up, Up, Up, Up],
+        [South, West, North, East],
+    ],
+    [ // north
+        [North, East, South, West],
+        [Up, Up, Up, Up],
+        [South, West, North, East],
+        [Down, Down, Down, Down],
+    ],
+    [ // south
+        [South, West, North, East],
+        [Down, Down, Down, Down],
+        [North, East, South, West],
+        [Up, Up, Up, Up],
+    ],
+    [ // up
+        [Up, Up, Up, Up],
+        [South, West, North, East],
+        [Down, Down, Down, Down],
+        [North, East, South, West],
+    ],
+    [ // east
+        [East, South, West, North],
+        [East, South, West, North],
+        [East, South, West, North],
+        [East, South, West, North],
+    ],
+];
+
+
+#[derive(Debug, Clone)]
+pub struct Face {
+    pub uv: [f32; 4],
+    pub texture: String,
+    pub cullface: Option<Direction>,
+    pub rotation: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Element {
+    from: [f32; 3],
+    to: [f32; 3],
+    faces: Vec<(Direction, Face)>,
+}
+
+impl Element {
+
+    fn get_vertex(&self, index: usize) -> [f32; 3] {
+        let mut v = self.from;
+        for (i, c) in v.iter_mut().enumerate() {
+            if index & (1 << i) > 0 {
+                *c = self.to[i];
+            }
+        }
+        v
+    }
+
+    fn get_face(&self, face: Direction) -> Option<&Face> {
+        self.faces.iter().find(|(d, _)| *d == face).map(|(_, f)| f)
+    }
+
+    fn get_face_vertex(&self, face: Direction) -> [[f32; 3]; 4] {
+        let index = face.vertex_index();
+        [self.get_vertex(index[0]), self.get_vertex(index[1]), self.get_vertex(index[2]), self.get_vertex(index[3])]
+    }
+}
+
+
+#[derive(Deserialize)]
+struct FaceRaw {
+    uv: Option<[f32; 4]>,
+    texture: Option<String>,
+    cullface: Option<String>,
+    rotation: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct ElementRaw {
+    from: [f32; 3],
+    to: [f32; 3],
+    #[serde(default)]
+    faces: HashMap<String, FaceRaw>,
+}
+
+#[derive(Deserialize)]
+struct ModelRaw {
+    parent: Option<String>,
+    #[serde(default)]
+    textures: HashMap<String, Value>,
+    elements: Option<Vec<ElementRaw>>,
+}
+
+/**
+ * one model file, before its parents are applied
+ */
+pub struct ModelDef {
+    pub parent: Option<String>,
+    textures: HashMap<String, String>,
+    elements: Option<Vec<Element>>,
+}
+
+impl ModelDef {
+
+    pub fn from_json(value: Value) -> Result<Self, serde_json::Error> {
+        let raw: ModelRaw = serde_json::from_value(value)?;
+        let textures = raw.textures.into_iter().filter_map(|(k, v)| {
+            match v {
+                Value::String(s) => Some((k, s)),
+                Value::Object(mut o) => match o.remove("sprite") {
+                    Some(Value::String(s)) => Some((k, s)),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }).collect();
+        let elements = raw.elements.map(|elements| elements.into_iter().map(|e| {
+            let faces = e.faces.into_iter().filter_map(|(k, f)| {
+                Some((Direction::from_name(k.as_str())?, Face {
+                    uv: f.uv.unwrap_or([0.0, 0.0, 16.0, 16.0]),
+                    texture: f.texture?,
+                    cullface: f.cullface.as_deref().and_then(Direction::from_name),
+                    rotation: f.rotation.unwrap_or(0),
+                }))
+            }).collect();
+            Element { from: e.from, to: e.to, faces }
+        }).collect());
+        Ok(ModelDef {
+            parent: raw.parent,
+            textures,
+            elements,
+        })
+    }
+}
+
+
+/**
+ * model with parents applied and texture variables resolved
+ */
+pub struct Model {
+    elements: Vec<Element>,
+}
+
+impl Model {
+
+    /// `chain` starts with the model itself and ends with the root parent
+    pub fn link(chain: &[&ModelDef]) -> Self {
+        let mut textures = HashMap::new();
+        let mut elements = None;
+        for def in chain.iter().rev() {
+            for (k, v) in &def.textures {
+                textures.insert(k.clone(), v.clone());
+            }
+            if def.elements.is_some() {
+                elements = def.elements.as_ref();
+            }
+        }
+        let mut elements: Vec<Element> = elements.cloned().unwrap_or_default();
+        for element in elements.iter_mut() {
+            for (_, face) in element.faces.iter_mut() {
+                let mut depth = 0;
+                while face.texture.starts_with('#') && depth < 16 {
+                    match textures.get(&face.texture[1..]) {
+                        Some(t) => face.texture = t.clone(),
+                        None => break,
+                    }
+                    depth += 1;
+                }
+            }
+        }
+        Model {
+            elements,
+        }
+    }
+
+    pub fn get_faces(&self, face: Direction) -> Vec<([[f32; 3]; 4], Face)> {
+        self.elements.iter().filter_map(|e| {
+            Some((e.get_face_vertex(face), e.get_face(face)?.clone()))
+        }).collect()
+    }
+}
+
+
+impl AppliedModel {
+
+    /// faces of the rotated model that look at `face`
+    pub fn get_faces(&self, model: &Model, face: Direction) -> Vec<([[f32; 3]; 4], Face)> {
+        let x = (self.x.rem_euclid(360) / 90) as usize;
+        let y = (self.y.rem_euclid(360) / 90) as usize;
+        let original_face = face.rotate_inv(x, y);
+        let columns = [East.rotate(x, y).axis(), Up.rotate(x, y).axis(), South.rotate(x, y).axis()];
+        let mut faces = model.get_faces(original_face);
+        for (vs, fs) in faces.iter_mut() {
+            for v in vs.iter_mut() {
+                let d = [v[0] - 8.0, v[1] - 8.0, v[2] - 8.0];
+                for (i, c) in v.iter_mut().enumerate() {
+                    *c = columns[0][i] * d[0] + columns[1][i] * d[1] + columns[2][i] * d[2] + 8.0;
+                }
+            }
+            fs.cullface = fs.cullface.map(|c| c.rotate(x, y));
+            if self.uvlock {
+                let (fx, fy) = face.rotate_factor();
+                fs.rotation = (fs.rotation + 720 - x as i32 * fx - y as i32 * fy).rem_euclid(360);
+            }
+        }
+        faces
+    }
+}
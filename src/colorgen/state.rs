@@ -0,0 +1,311 @@
+use std::collections::btree_map::BTreeMap as Map;
+
+use serde_json::Value;
+
+use crate::color::blockstate::BlockState;
+use crate::color::de::BlockStateC;
+
+
+#[derive(Debug, Clone)]
+pub struct AppliedModel {
+    pub model: String,
+    pub x: i32,
+    pub y: i32,
+    pub uvlock: bool,
+}
+
+impl AppliedModel {
+
+    fn parse_one(value: &Value) -> Option<Self> {
+        Some(AppliedModel {
+            model: String::from(value.get("model")?.as_str()?),
+            x: value.get("x").and_then(Value::as_i64).unwrap_or(0) as i32,
+            y: value.get("y").and_then(Value::as_i64).unwrap_or(0) as i32,
+            uvlock: value.get("uvlock").and_then(Value::as_bool).unwrap_or(false),
+        })
+    }
+
+    /// a list of weighted models is reduced to the first one with the largest weight
+    pub fn from_json(value: &Value) -> Option<Self> {
+        if let Some(list) = value.as_array() {
+            let mut maxw = 0;
+            let mut selected = None;
+            for one in list {
+                let w = one.get("weight").and_then(Value::as_i64).unwrap_or(1);
+                if maxw < w {
+                    maxw = w;
+                    selected = Some(one);
+                }
+            }
+            Self::parse_one(selected?)
+        } else {
+            Self::parse_one(value)
+        }
+    }
+}
+
+
+/**
+ * assign model ids in order; id 0 is reserved for "no model";
+ * one id can stand for several applied models drawn together
+ */
+#[derive(Default)]
+pub struct IdGen {
+    cache: Vec<Vec<AppliedModel>>,
+}
+
+impl IdGen {
+
+    pub fn generate(&mut self, applied_model: AppliedModel) -> usize {
+        self.generate_parts(vec![applied_model])
+    }
+
+    pub fn generate_parts(&mut self, parts: Vec<AppliedModel>) -> usize {
+        self.cache.push(parts);
+        self.cache.len()
+    }
+
+    pub fn total(&self) -> usize {
+        self.cache.len() + 1
+    }
+
+    pub fn get(&self, id: usize) -> Option<&[AppliedModel]> {
+        self.cache.get(id.checked_sub(1)?).map(Vec::as_slice)
+    }
+}
+
+
+/**
+ * properties in order of appearance, each value gets one bit
+ */
+#[derive(Default)]
+struct PropKeys {
+    props: Vec<(String, Vec<String>)>,
+}
+
+impl PropKeys {
+
+    fn add(&mut self, name: &str, value: &str) {
+        if let Some((_, values)) = self.props.iter_mut().find(|(n, _)| n == name) {
+            if !values.iter().any(|v| v == value) {
+                values.push(String::from(value));
+            }
+        } else {
+            self.props.push((String::from(name), vec![String::from(value)]));
+        }
+    }
+
+    fn gen_key(&self) -> Option<Map<String, usize>> {
+        let mut keys = Map::new();
+        let mut bit = 0;
+        for (name, values) in &self.props {
+            for value in values {
+                if bit >= usize::BITS {
+                    return None;
+                }
+                keys.insert(format!("{}={}", name, value), 1 << bit);
+                bit += 1;
+            }
+        }
+        Some(keys)
+    }
+}
+
+fn value_str(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn conditions(when: &Value) -> Vec<&serde_json::Map<String, Value>> {
+    if let Some(or) = when.get("OR").and_then(Value::as_array) {
+        or.iter().filter_map(Value::as_object).collect()
+    } else {
+        when.as_object().into_iter().collect()
+    }
+}
+
+/// masks of every combination in one condition, `a|b` values multiply the masks
+fn cal_key(cond: &serde_json::Map<String, Value>, keys: &Map<String, usize>) -> Vec<usize> {
+    let mut masks = vec![0];
+    for (name, value) in cond {
+        let value = value_str(value);
+        let mut new_masks = Vec::with_capacity(masks.len());
+        for v in value.split('|') {
+            let key = keys.get(format!("{}={}", name, v).as_str()).cloned().unwrap_or(0);
+            new_masks.extend(masks.iter().map(|m| m | key));
+        }
+        masks = new_masks;
+    }
+    masks
+}
+
+fn parse_variants(variants: &serde_json::Map<String, Value>, idgen: &mut IdGen) -> Option<BlockStateC> {
+    if let Some(v) = variants.get("") {
+        return Some(BlockState::build_single(idgen.generate(AppliedModel::from_json(v)?)));
+    }
+    let mut props = PropKeys::default();
+    for k in variants.keys() {
+        for p in k.split(',') {
+            let mut sp = p.splitn(2, '=');
+            if let (Some(name), Some(value)) = (sp.next(), sp.next()) {
+                props.add(name, value);
+            }
+        }
+    }
+    let keys = props.gen_key()?;
+    let mut values = Map::new();
+    for (k, v) in variants {
+        let mask = k.split(',').fold(0, |m, p| m | keys.get(p).cloned().unwrap_or(0));
+        if let Some(applied_model) = AppliedModel::from_json(v) {
+            values.insert(mask, idgen.generate(applied_model));
+        }
+    }
+    Some(BlockState::build_variants(keys, values))
+}
+
+fn parse_multipart(parts: &[Value], idgen: &mut IdGen) -> Option<BlockStateC> {
+    let mut props = PropKeys::default();
+    for part in parts {
+        if let Some(when) = part.get("when") {
+            for cond in conditions(when) {
+                for (name, value) in cond {
+                    for v in value_str(value).split('|') {
+                        props.add(name, v);
+                    }
+                }
+            }
+        }
+    }
+    let keys = props.gen_key()?;
+    // the index keeps one model per mask, so the parts sharing a mask are drawn as one model
+    let mut applied = Vec::with_capacity(parts.len());
+    let mut mask_parts: Map<usize, Vec<usize>> = Map::new();
+    for part in parts {
+        let applied_model = match part.get("apply").and_then(AppliedModel::from_json) {
+            Some(applied_model) => applied_model,
+            None => continue,
+        };
+        let mut when = Vec::new();
+        if let Some(w) = part.get("when") {
+            for cond in conditions(w) {
+                when.extend(cal_key(cond, &keys));
+            }
+        }
+        if when.is_empty() {
+            when.push(0);
+        }
+        for mask in when {
+            let list = mask_parts.entry(mask).or_default();
+            if !list.contains(&applied.len()) {
+                list.push(applied.len());
+            }
+        }
+        applied.push(applied_model);
+    }
+    let mut values: Vec<(Vec<usize>, Vec<usize>)> = Vec::new();
+    for (mask, list) in mask_parts {
+        match values.iter_mut().find(|(_, l)| *l == list) {
+            Some((when, _)) => when.push(mask),
+            None => values.push((vec![mask], list)),
+        }
+    }
+    values.sort_by_key(|(_, list)| list.clone());
+    let values = values.into_iter()
+        .map(|(when, list)| (when, idgen.generate_parts(list.iter().map(|i| applied[*i].clone()).collect())))
+        .collect();
+    Some(BlockState::build_multipart(keys, values))
+}
+
+/// parse a blockstate file; models get ids from `idgen`
+pub fn parse_blockstate(json: &Value, idgen: &mut IdGen) -> Option<BlockStateC> {
+    if let Some(variants) = json.get("variants").and_then(Value::as_object) {
+        return parse_variants(variants, idgen);
+    }
+    if let Some(parts) = json.get("multipart").and_then(Value::as_array) {
+        return parse_multipart(parts.as_slice(), idgen);
+    }
+    None
+}
+
+
+mod test {
+
+    #[test]
+    fn test_parse_blockstate_roundtrip() {
+        use super::IdGen;
+        use super::parse_blockstate;
+        use crate::color::de::BlockStateC;
+
+        let mut idgen = IdGen::default();
+        let variants = serde_json::json!({
+            "variants": {
+                "facing=east,half=bottom": { "model": "block/stairs", "y": 0 },
+                "facing=west,half=bottom": { "model": "block/stairs", "y": 180, "uvlock": true },
+                "facing=east,half=top": [{ "model": "block/stairs_a" }, { "model": "block/stairs_b", "weight": 5 }]
+            }
+        });
+        let bs = parse_blockstate(&variants, &mut idgen).unwrap();
+        let text = serde_json::to_string(&bs).unwrap();
+        let bs: BlockStateC = serde_json::from_str(text.as_str()).unwrap();
+        let id = bs.get(["half=top", "facing=east"].iter().copied())[0];
+        assert_eq!(idgen.get(id).unwrap()[0].model, "block/stairs_b");
+        let id = bs.get(["facing=west", "half=bottom"].iter().copied())[0];
+        assert!(idgen.get(id).unwrap()[0].uvlock);
+
+        let multipart = serde_json::json!({
+            "multipart": [
+                { "apply": { "model": "block/fence_post" } },
+                { "when": { "north": "true" }, "apply": { "model": "block/fence_side" } },
+                { "when": { "OR": [{ "east": "true" }, { "west": "low|tall" }] }, "apply": { "model": "block/fence_ew" } }
+            ]
+        });
+        let bs = parse_blockstate(&multipart, &mut idgen).unwrap();
+        let text = serde_json::to_string(&bs).unwrap();
+        let bs: BlockStateC = serde_json::from_str(text.as_str()).unwrap();
+        let models: Vec<_> = bs.get(["north=true", "east=false", "west=tall"].iter().copied())
+            .into_iter()
+            .map(|id| idgen.get(id).unwrap()[0].model.clone())
+            .collect();
+        assert_eq!(models, vec!["block/fence_post", "block/fence_side", "block/fence_ew"]);
+        assert_eq!(idgen.total(), 7);
+    }
+
+    #[test]
+    fn test_parse_multipart_same_when() {
+        use super::IdGen;
+        use super::parse_blockstate;
+
+        let mut idgen = IdGen::default();
+        let multipart = serde_json::json!({
+            "multipart": [
+                { "apply": { "model": "block/post" } },
+                { "apply": { "model": "block/post_top" } },
+                { "when": { "north": "true" }, "apply": { "model": "block/side" } },
+                { "when": { "OR": [{ "north": "true" }, { "south": "true" }] }, "apply": { "model": "block/rail" } }
+            ]
+        });
+        let bs = parse_blockstate(&multipart, &mut idgen).unwrap();
+        // a group not matching the state gives the part without condition again, so only distinct ones are compared
+        let models = |key: &[&str]| -> Vec<Vec<String>> {
+            let mut models: Vec<Vec<String>> = bs.get(key.iter().copied())
+                .into_iter()
+                .map(|id| idgen.get(id).unwrap().iter().map(|m| m.model.clone()).collect())
+                .collect();
+            models.sort();
+            models.dedup();
+            models
+        };
+        assert_eq!(models(&["north=false", "south=false"]), vec![vec!["block/post", "block/post_top"]]);
+        assert_eq!(models(&["north=true", "south=false"]), vec![
+            vec!["block/post", "block/post_top"],
+            vec!["block/side", "block/rail"],
+        ]);
+        assert_eq!(models(&["north=false", "south=true"]), vec![
+            vec!["block/post", "block/post_top"],
+            vec!["block/rail"],
+        ]);
+    }
+
+}
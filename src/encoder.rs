@@ -0,0 +1,216 @@
+use std::io;
+use std::str::FromStr;
+use std::collections::HashMap;
+
+use image::ColorType;
+use image::ImageError;
+use image::ImageResult;
+use image::RgbaImage;
+use image::buffer::ConvertBuffer;
+use image::RgbImage;
+use image::codecs::png::PngEncoder;
+use image::codecs::png::CompressionType;
+use image::codecs::png::FilterType;
+
+
+/**
+ * output format of rendered pictures and tiles
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TileEncoder {
+    /// png with default compression; opaque pictures are saved as RGB
+    #[default]
+    Png,
+    /// png with best compression; pictures with at most 256 colors are saved with a palette
+    PngOptimized,
+    WebpLossless,
+    /// lossy webp with quality from 0 to 100
+    WebpLossy(f32),
+}
+
+impl FromStr for TileEncoder {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sp = s.splitn(2, ':');
+        match (sp.next().unwrap(), sp.next()) {
+            ("png", None) => Ok(TileEncoder::Png),
+            ("png-opt", None) => Ok(TileEncoder::PngOptimized),
+            ("webp", None) => Ok(TileEncoder::WebpLossless),
+            ("webp", Some(quality)) => {
+                let quality: f32 = quality.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "quality"))?;
+                if !(0.0 ..= 100.0).contains(&quality) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "quality"));
+                }
+                Ok(TileEncoder::WebpLossy(quality))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported")),
+        }
+    }
+}
+
+fn is_opaque(img: &RgbaImage) -> bool {
+    img.pixels().all(|p| p[3] == 255)
+}
+
+/// palette of the picture, `None` if it has more than 256 colors
+fn palette(img: &RgbaImage) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let mut colors: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut list = Vec::new();
+    let mut indices = Vec::with_capacity((img.width() * img.height()) as usize);
+    for p in img.pixels() {
+        let i = match colors.get(&p.0) {
+            Some(i) => *i,
+            None => {
+                if list.len() == 256 {
+                    return None;
+                }
+                let i = list.len() as u8;
+                colors.insert(p.0, i);
+                list.push(p.0);
+                i
+            }
+        };
+        indices.push(i);
+    }
+    Some((list, indices))
+}
+
+fn encode_indexed(img: &RgbaImage, colors: &[[u8; 4]], indices: &[u8]) -> Result<Vec<u8>, png::EncodingError> {
+    let (depth, bits) = match colors.len() {
+        0 ..= 2 => (png::BitDepth::One, 1),
+        3 ..= 4 => (png::BitDepth::Two, 2),
+        5 ..= 16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let width = img.width() as usize;
+    let per_byte = 8 / bits;
+    let row_bytes = (width + per_byte - 1) / per_byte;
+    let mut data = vec![0u8; row_bytes * img.height() as usize];
+    for (y, row) in indices.chunks(width).enumerate() {
+        for (x, i) in row.iter().enumerate() {
+            let shift = 8 - bits * (x % per_byte + 1);
+            data[y * row_bytes + x / per_byte] |= i << shift;
+        }
+    }
+    let mut plte = Vec::with_capacity(colors.len() * 3);
+    let mut trns: Vec<u8> = colors.iter().map(|c| c[3]).collect();
+    for c in colors {
+        plte.extend_from_slice(&c[0..3]);
+    }
+    while trns.last() == Some(&255) {
+        trns.pop();
+    }
+    let mut buf = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut buf, img.width(), img.height());
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(depth);
+        encoder.set_palette(plte);
+        if !trns.is_empty() {
+            encoder.set_trns(trns);
+        }
+        encoder.set_compression(png::Compression::Best);
+        encoder.set_filter(png::FilterType::NoFilter);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(data.as_slice())?;
+    }
+    Ok(buf)
+}
+
+fn encode_png(img: &RgbaImage, compression: CompressionType, filter: FilterType) -> ImageResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    let encoder = PngEncoder::new_with_quality(&mut buf, compression, filter);
+    if is_opaque(img) {
+        let rgb: RgbImage = img.convert();
+        encoder.encode(rgb.as_raw(), img.width(), img.height(), ColorType::Rgb8)?;
+    } else {
+        encoder.encode(img.as_raw(), img.width(), img.height(), ColorType::Rgba8)?;
+    }
+    Ok(buf)
+}
+
+impl TileEncoder {
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TileEncoder::Png | TileEncoder::PngOptimized => "png",
+            TileEncoder::WebpLossless | TileEncoder::WebpLossy(_) => "webp",
+        }
+    }
+
+    /// whether decoding the output gives the same pixels
+    pub fn is_lossless(&self) -> bool {
+        !matches!(self, TileEncoder::WebpLossy(_))
+    }
+
+    pub fn encode(&self, img: &RgbaImage) -> ImageResult<Vec<u8>> {
+        match self {
+            TileEncoder::Png => encode_png(img, CompressionType::Fast, FilterType::Sub),
+            TileEncoder::PngOptimized => {
+                if let Some((colors, indices)) = palette(img) {
+                    encode_indexed(img, colors.as_slice(), indices.as_slice())
+                        .map_err(|e| ImageError::IoError(io::Error::new(io::ErrorKind::Other, e.to_string())))
+                } else {
+                    encode_png(img, CompressionType::Best, FilterType::Paeth)
+                }
+            }
+            TileEncoder::WebpLossless => {
+                Ok(webp::Encoder::from_rgba(img.as_raw(), img.width(), img.height()).encode_lossless().to_vec())
+            }
+            TileEncoder::WebpLossy(quality) => {
+                Ok(webp::Encoder::from_rgba(img.as_raw(), img.width(), img.height()).encode(*quality).to_vec())
+            }
+        }
+    }
+}
+
+/// same size and same pixels; fully transparent pixels are equal whatever their color is
+pub fn same_pixels(a: &RgbaImage, b: &RgbaImage) -> bool {
+    a.dimensions() == b.dimensions() && a.pixels().zip(b.pixels()).all(|(p, q)| p == q || (p[3] == 0 && q[3] == 0))
+}
+
+/// decode png, webp or any other format known to `image`
+pub fn decode(buf: &[u8]) -> ImageResult<RgbaImage> {
+    if buf.len() > 12 && &buf[0..4] == b"RIFF" && &buf[8..12] == b"WEBP" {
+        let img = webp::Decoder::new(buf).decode()
+            .ok_or_else(|| ImageError::IoError(io::Error::new(io::ErrorKind::InvalidData, "webp")))?;
+        let (w, h) = (img.width(), img.height());
+        let img = if img.is_alpha() {
+            RgbaImage::from_raw(w, h, img.to_vec())
+        } else {
+            RgbImage::from_raw(w, h, img.to_vec()).map(|img| img.convert())
+        };
+        img.ok_or_else(|| ImageError::IoError(io::Error::new(io::ErrorKind::InvalidData, "webp")))
+    } else {
+        Ok(image::load_from_memory(buf)?.to_rgba8())
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_encode_roundtrip() {
+        use super::TileEncoder;
+        use super::decode;
+        use super::same_pixels;
+        use std::str::FromStr;
+        use image::Rgba;
+        use image::RgbaImage;
+
+        let img = RgbaImage::from_fn(37, 5, |x, y| Rgba::from([(x % 3 * 80) as u8, y as u8, 7, if x == 0 { 0 } else { 255 }]));
+        for name in &["png", "png-opt", "webp"] {
+            let encoder = TileEncoder::from_str(name).unwrap();
+            let data = encoder.encode(&img).unwrap();
+            assert!(same_pixels(&decode(data.as_slice()).unwrap(), &img), "{}", name);
+        }
+        let many = RgbaImage::from_fn(20, 20, |x, y| Rgba::from([x as u8, y as u8, 0, 255]));
+        let data = TileEncoder::PngOptimized.encode(&many).unwrap();
+        assert_eq!(decode(data.as_slice()).unwrap(), many);
+        assert_eq!(TileEncoder::from_str("webp:75").unwrap(), TileEncoder::WebpLossy(75.0));
+        assert!(TileEncoder::from_str("webp:101").is_err());
+        assert!(TileEncoder::from_str("jpeg").is_err());
+    }
+
+}
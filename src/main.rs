@@ -3,14 +3,21 @@ mod color;
 mod render;
 mod application;
 mod tilegen;
+mod colorgen;
+mod encoder;
 
 #[cfg(feature = "service")]
 mod service;
 
 use std::env;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::fs::File;
+use std::io::BufWriter;
 use std::sync::Arc;
 use std::time::Instant;
+use std::process;
+use std::fmt::Display;
 use std::str::FromStr;
 
 use clap::App;
@@ -25,6 +32,18 @@ const AUTHORS: &'static str = env!("CARGO_PKG_AUTHORS");
 
 const MAX_THREAD: usize = 16;
 
+/// logs why the value of `--<option>` is refused and exits with status 2
+fn invalid_value<T>(option: &str, e: impl Display) -> T {
+    log::error!("invalid --{}: {}", option, e);
+    process::exit(2)
+}
+
+/// logs why the command failed and exits with status 1
+fn failed<T>(e: impl Display) -> T {
+    log::error!("{}", e);
+    process::exit(1)
+}
+
 fn main() {
 
     if let Err(_e) = env::var("RUST_LOG") {
@@ -67,6 +86,24 @@ fn main() {
                 .help("gamma for gamma correction, default is 1.0")
                 .takes_value(true)
             )
+            .arg(
+                Arg::with_name("mode")
+                .long("mode")
+                .help("what the pictures show, can be \"color\" or \"height\" (surface height in gray); default is \"color\"")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("shading")
+                .long("shading")
+                .help("relief over the colors, can be \"none\" or \"slope\" (brighter where the surface rises to the south); default is \"none\"")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("hide")
+                .long("hide")
+                .help("layers not drawn, comma separated: \"seafloor\", \"transparent\", \"foliage\"")
+                .takes_value(true)
+            )
             .arg(
                 Arg::with_name("thread")
                 .short("t")
@@ -74,6 +111,30 @@ fn main() {
                 .help("multi-thread: thread number")
                 .takes_value(true)
             )
+            .arg(
+                Arg::with_name("color_override")
+                .long("color_override")
+                .help("json file of block colors used before colormap")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("tint_rules")
+                .long("tint_rules")
+                .help("json file of block name patterns mapped to biome tint and fallback color")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("encoder")
+                .long("encoder")
+                .help("output format, can be \"png\", \"png-opt\" (best compression, palette if at most 256 colors), \"webp\" (lossless), \"webp:<quality>\" (lossy, quality from 0 to 100); default is \"png\"")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("profile")
+                .long("profile")
+                .help("name of the resource profile, colors are read from `resource/<profile>/` instead of `resource/`")
+                .takes_value(true)
+            )
         );
     let app = app.subcommand(
             SubCommand::with_name("tile")
@@ -102,7 +163,7 @@ fn main() {
             .arg(
                 Arg::with_name("path_mode")
                 .long("path_mode")
-                .help("generated path mode, can be \"layer+\", \"layer+:<minZoom>\", \"layer+:<minZoom>,<maxZoom>\", \"layer-\", \"layer-:<minZoom>\", \"layer-:<maxZoom>,<minZoom>\"")
+                .help("generated path mode, can be \"layer+\", \"layer+:<minZoom>\", \"layer+:<minZoom>,<maxZoom>\", \"layer-\", \"layer-:<minZoom>\", \"layer-:<maxZoom>,<minZoom>\", \"tree\", \"tree:<topleft>,<topright>,<bottomleft>,<bottomright>\", \"xyz\", \"xyz:<maxZoom>\"")
                 .takes_value(true)
                 .required(true)
             )
@@ -117,6 +178,146 @@ fn main() {
                 .long("check_exist")
                 .help("check if the same picture exist and then skip rewrite it")
             )
+            .arg(
+                Arg::with_name("viewer")
+                .long("viewer")
+                .help("write a leaflet viewer `index.html` into output folder; not with \"mbtiles\"")
+            )
+            .arg(
+                Arg::with_name("overzoom")
+                .long("overzoom")
+                .help("levels of enlarged tiles beyond the original scale, 1 (2x), 2 (4x) or 3 (8x); default is 0")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("encoder")
+                .long("encoder")
+                .help("output format, can be \"png\", \"png-opt\" (best compression, palette if at most 256 colors), \"webp\" (lossless), \"webp:<quality>\" (lossy, quality from 0 to 100); default is \"png\"")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("output_type")
+                .long("output_type")
+                .help("how tiles are stored, can be \"dir\", \"zip\" (one archive), \"mbtiles\" (sqlite); with \"zip\" or \"mbtiles\", output_dir is the archive file; default is \"dir\"")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("memory_hint")
+                .long("memory_hint")
+                .help("MB of decoded images the parallel branches should fit in; only lowers the number of workers, at least one, and does not limit a branch")
+                .takes_value(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("stitch")
+            .arg(
+                Arg::with_name("input_dir")
+                .short("i")
+                .long("input_dir")
+                .help("input folder of rendered tiles")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("output_file")
+                .short("o")
+                .long("output_file")
+                .help("output png file")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("range")
+                .short("r")
+                .long("range")
+                .help("tile range \"<xmin>,<zmin>,<xmax>,<zmax>\" (inclusive) or \"max\"; default is \"max\"")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("downscale")
+                .short("d")
+                .long("downscale")
+                .help("shrink each tile by this factor, must divide tile size; default is 1")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("filter")
+                .long("filter")
+                .help("filter used in downscale, can be \"nearest\", \"triangle\", \"gaussian\", \"catmullrom\", \"lanczos3\"; default is \"triangle\"")
+                .takes_value(true)
+            )
+        );
+    let app = app.subcommand(
+            SubCommand::with_name("diagnose")
+            .arg(
+                Arg::with_name("input_dir")
+                .short("i")
+                .long("input_dir")
+                .help("input folder")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("color_override")
+                .long("color_override")
+                .help("json file of block colors used before colormap")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("tint_rules")
+                .long("tint_rules")
+                .help("json file of block name patterns mapped to biome tint and fallback color")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("profile")
+                .long("profile")
+                .help("name of the resource profile, colors are read from `resource/<profile>/` instead of `resource/`")
+                .takes_value(true)
+            )
+        );
+    let app = app.subcommand(
+            SubCommand::with_name("gen-colormap")
+            .arg(
+                Arg::with_name("assets")
+                .help("resource packs or version jar, later ones replace earlier ones")
+                .multiple(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("output_dir")
+                .short("o")
+                .long("output_dir")
+                .help("output folder")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("linewidth")
+                .short("w")
+                .long("linewidth")
+                .help("number of baked-model each row; default is 32")
+                .takes_value(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("gen-biomes")
+            .arg(
+                Arg::with_name("mc_version")
+                .short("m")
+                .long("mc_version")
+                .help("minecraft version of the biome table")
+                .takes_value(true)
+                .possible_values(&colorgen::biomes::VERSIONS)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("output file; default is \"biome.json\"")
+                .takes_value(true)
+            )
         );
     #[cfg(feature = "service")]
     let app = app.subcommand(
@@ -155,6 +356,69 @@ fn main() {
                 .takes_value(true)
                 .required(false)
             )
+            .arg(
+                Arg::with_name("job_workers")
+                .long("job_workers")
+                .help("number of jobs from `/jobs` running at the same time; default is 2")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("job_retention")
+                .long("job_retention")
+                .help("seconds the result of a finished job is kept; default is 3600")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("worlds")
+                .long("worlds")
+                .help("folder of the worlds hosted by the server; if not set, world hosting is disabled")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("auth")
+                .long("auth")
+                .help("token config file; if set, rendering, jobs and uploads need a bearer token")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("shutdown_timeout")
+                .long("shutdown_timeout")
+                .help("seconds in-flight renders and running jobs are waited for on SIGTERM; default is 30")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("watch_resources")
+                .long("watch_resources")
+                .help("seconds between checks of the resource files, reloaded when changed; if not set, they are only reloaded by `/admin/reload`, which needs `--auth`")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("cache_memory")
+                .long("cache_memory")
+                .help("MB of pictures rendered by `/render` kept in memory; 0 disables it; default is 64")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("cache_dir")
+                .long("cache_dir")
+                .help("folder keeping pictures rendered by `/render` across restarts; if not set, they are only kept in memory")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(
+                Arg::with_name("cache_disk")
+                .long("cache_disk")
+                .help("MB of pictures kept in `cache_dir`; default is 1024")
+                .takes_value(true)
+                .required(false)
+            )
         );
 
     let matches = app.get_matches();
@@ -183,6 +447,34 @@ fn main() {
                         options.render_option_mut().set_env_light(lit);
                     } 
                 }
+                if let Some(value) = args.value_of("mode") {
+                    options.render_option_mut().set_mode(render::RenderMode::from_str(value).unwrap_or_else(|e| invalid_value("mode", e)));
+                }
+                if let Some(value) = args.value_of("shading") {
+                    options.render_option_mut().set_shading(render::Shading::from_str(value).unwrap_or_else(|e| invalid_value("shading", e)));
+                }
+                if let Some(value) = args.value_of("hide") {
+                    for layer in value.split(',') {
+                        match layer.trim() {
+                            "seafloor" => options.render_option_mut().set_seafloor(false),
+                            "transparent" => options.render_option_mut().set_transparent(false),
+                            "foliage" => options.render_option_mut().set_foliage(false),
+                            other => invalid_value("hide", format!("unknown layer {:?}", other)),
+                        }
+                    }
+                }
+                if let Some(path) = args.value_of("color_override") {
+                    options.set_color_override(path);
+                }
+                if let Some(path) = args.value_of("tint_rules") {
+                    options.set_tint_rules(path);
+                }
+                if let Some(value) = args.value_of("encoder") {
+                    options.set_encoder(encoder::TileEncoder::from_str(value).unwrap_or_else(|e| invalid_value("encoder", e)));
+                }
+                if let Some(profile) = args.value_of("profile") {
+                    options.set_profile(profile);
+                }
                 if let Some(thread) = args.value_of("thread") {
                     if let Ok(thread) = thread.parse() {
                         if thread <= MAX_THREAD {
@@ -193,7 +485,7 @@ fn main() {
                 options
             };
             
-            let app = Arc::new(application::Application::new(options));
+            let app = Arc::new(application::Application::new(options).unwrap_or_else(failed));
             let time = Instant::now();
             let list = app.list_files();
             application::Application::alloc_tasks(app, list);
@@ -217,6 +509,29 @@ fn main() {
                 if args.is_present("check_exist") {
                     options.set_check_exist(true);
                 }
+                if args.is_present("viewer") {
+                    options.set_viewer(true);
+                }
+                if let Some(overzoom) = args.value_of("overzoom") {
+                    if let Ok(overzoom) = overzoom.parse() {
+                        options.set_overzoom(overzoom);
+                    }
+                }
+                if let Some(value) = args.value_of("encoder") {
+                    options.set_encoder(encoder::TileEncoder::from_str(value).unwrap_or_else(|e| invalid_value("encoder", e)));
+                }
+                if let Some(value) = args.value_of("output_type") {
+                    let store_mode = tilegen::store::StoreMode::from_str(value).unwrap_or_else(|e| invalid_value("output_type", e));
+                    if store_mode == tilegen::store::StoreMode::MBTiles && args.is_present("viewer") {
+                        invalid_value::<()>("viewer", "a viewer cannot be served from mbtiles");
+                    }
+                    options.set_store_mode(store_mode);
+                }
+                if let Some(memory_hint) = args.value_of("memory_hint") {
+                    if let Ok(memory_hint) = memory_hint.parse() {
+                        options.set_memory_hint(memory_hint);
+                    }
+                }
                 options
             };
             let app = tilegen::TileGenerator::new(options);
@@ -228,6 +543,110 @@ fn main() {
             log::info!("> used {}ms", time.as_millis());
         }
 
+        "diagnose" => {
+            let options = {
+                let mut options = application::AppOptions::default();
+                options.set_input_folder(args.value_of("input_dir").unwrap());
+                if let Some(path) = args.value_of("color_override") {
+                    options.set_color_override(path);
+                }
+                if let Some(path) = args.value_of("tint_rules") {
+                    options.set_tint_rules(path);
+                }
+                if let Some(profile) = args.value_of("profile") {
+                    options.set_profile(profile);
+                }
+                options
+            };
+
+            let app = application::Application::new(options).unwrap_or_else(failed);
+            let time = Instant::now();
+            let list = app.list_files();
+            let mut columns = BTreeMap::new();
+            for task in &list {
+                if let Err(e) = app.diagnose_one(task.src.as_path(), &task.tile_id, &mut columns) {
+                    log::warn!("tile{:?} error: {}", task.tile_id, e);
+                }
+            }
+            let diagnostics = app.color_manager().diagnostics();
+            if diagnostics.is_empty() {
+                println!("all blocks in {} tiles have colors", list.len());
+            } else {
+                println!("missing blocks:");
+                for (name, _) in diagnostics.missing_blocks() {
+                    println!("    {}\t{} columns", name, columns.get(&name).cloned().unwrap_or(0));
+                }
+                println!("unmatched states:");
+                for (name, states) in diagnostics.unmatched_states() {
+                    println!("    {}\t{} columns", name, columns.get(&name).cloned().unwrap_or(0));
+                    for state in states {
+                        println!("        [{}]", state);
+                    }
+                }
+            }
+            let time = Instant::now() - time;
+            log::info!("> used {}ms", time.as_millis());
+        }
+
+        "stitch" => {
+            let options = {
+                let input_folder = PathBuf::from(args.value_of("input_dir").unwrap());
+                let output_file = PathBuf::from(args.value_of("output_file").unwrap());
+                let range = tilegen::stitch::StitchRange::from_str(args.value_of("range").unwrap_or("max")).unwrap_or_else(|e| invalid_value("range", e));
+                let mut options = tilegen::stitch::StitchOptions::new(input_folder, output_file, range);
+                if let Some(downscale) = args.value_of("downscale") {
+                    if let Ok(downscale) = downscale.parse() {
+                        options.set_downscale(downscale);
+                    }
+                }
+                if let Some(value) = args.value_of("filter") {
+                    options.set_filter(value);
+                }
+                options
+            };
+            let app = tilegen::stitch::Stitcher::new(options);
+            let time = Instant::now();
+            match app.stitch() {
+                Ok((w, h)) => log::info!("> stitched {}x{}", w, h),
+                Err(e) => failed(format!("stitch fail: {}", e)),
+            }
+            let time = Instant::now() - time;
+            log::info!("> used {}ms", time.as_millis());
+        }
+
+        "gen-colormap" => {
+            let options = {
+                let assets = args.values_of("assets").unwrap().map(PathBuf::from).collect();
+                let output_folder = PathBuf::from(args.value_of("output_dir").unwrap());
+                let mut options = colorgen::ColorGenOptions::new(assets, output_folder);
+                if let Some(linewidth) = args.value_of("linewidth") {
+                    if let Ok(linewidth) = linewidth.parse() {
+                        options.set_linewidth(linewidth);
+                    }
+                }
+                options
+            };
+            let app = colorgen::ColorMapGenerator::new(options);
+            let time = Instant::now();
+            if let Err(e) = app.generate() {
+                failed::<()>(format!("generate colormap fail: {}", e));
+            }
+            let time = Instant::now() - time;
+            log::info!("> used {}ms", time.as_millis());
+        }
+
+        "gen-biomes" => {
+            let version = args.value_of("mc_version").unwrap();
+            let output = PathBuf::from(args.value_of("output").unwrap_or("biome.json"));
+            let result = File::create(&output).and_then(|ofile| {
+                colorgen::biomes::write_biomes(version, BufWriter::new(ofile))
+            });
+            match result {
+                Ok(n) => log::info!("> write {} biomes of {} to {}", n, version, output.display()),
+                Err(e) => failed(format!("generate biomes fail: {}", e)),
+            }
+        }
+
         #[cfg(feature = "service")]
         "renderserver" => {
             let options = {
@@ -254,9 +673,54 @@ fn main() {
                         options.set_tls(PathBuf::from(a[0]), PathBuf::from(a[1]));
                     }
                 }
+                if let Some(job_workers) = args.value_of("job_workers") {
+                    if let Ok(num) = job_workers.parse() {
+                        options.set_job_workers(num);
+                    }
+                }
+                if let Some(job_retention) = args.value_of("job_retention") {
+                    if let Ok(secs) = job_retention.parse() {
+                        options.set_job_retention(secs);
+                    }
+                }
+                if let Some(worlds) = args.value_of("worlds") {
+                    options.set_worlds(PathBuf::from(worlds));
+                }
+                if let Some(auth) = args.value_of("auth") {
+                    options.set_auth(PathBuf::from(auth));
+                }
+                if let Some(shutdown_timeout) = args.value_of("shutdown_timeout") {
+                    if let Ok(secs) = shutdown_timeout.parse() {
+                        options.set_shutdown_timeout(secs);
+                    }
+                }
+                if let Some(watch_resources) = args.value_of("watch_resources") {
+                    if let Ok(secs) = watch_resources.parse() {
+                        options.set_watch_resources(secs);
+                    }
+                }
+                if let Some(cache_memory) = args.value_of("cache_memory") {
+                    if let Ok(mb) = cache_memory.parse() {
+                        options.set_cache_memory(mb);
+                    }
+                }
+                if let Some(cache_dir) = args.value_of("cache_dir") {
+                    options.set_cache_dir(PathBuf::from(cache_dir));
+                }
+                if let Some(cache_disk) = args.value_of("cache_disk") {
+                    if let Ok(mb) = cache_disk.parse() {
+                        options.set_cache_disk(mb);
+                    }
+                }
                 options
             };
-            service::RenderService::new(options).start();
+            match service::RenderService::new(options) {
+                Ok(service) => service.start(),
+                Err(e) => {
+                    log::error!("render server fail to start: {}", e);
+                    process::exit(1);
+                }
+            }
         }
 
         _ => {
@@ -6,6 +6,8 @@ use crate::color::biome::InnerColor;
 
 pub struct BlockProps {
 
+    pub name: String,
+
     pub air: bool,
 
     pub water: bool,
@@ -19,6 +21,7 @@ impl BlockProps {
 
     pub fn new() -> Self {
         BlockProps {
+            name: String::new(),
             air: true,
             water: false,
             waterlogged: false,
@@ -26,7 +29,7 @@ impl BlockProps {
         }
     }
 
-    pub fn new_from<'a, I: Iterator<Item = &'a str>>(name: &'a str, state: I) -> Self {
+    pub fn new_from<'a, I: Iterator<Item = &'a str>>(name: &'a str, state: I, biome_color: InnerColor) -> Self {
         let mut waterlogged = false;
         for s in state {
             let mut it = s.split('=');
@@ -37,10 +40,11 @@ impl BlockProps {
             }
         }
         BlockProps {
+            name: String::from(name),
             air: name == "minecraft:air",
             water: name == "minecraft:water",
             waterlogged,
-            biome_color: InnerColor::from(name)
+            biome_color
         }
     }
 }
@@ -95,6 +99,7 @@ impl<'a> TryFrom<&'a str> for KeyLine<'a> {
 }
 
 
+#[derive(Clone)]
 pub struct SplitIter<'a>(Option<Split<'a, char>>);
 
 impl<'a> From<Option<&'a str>> for SplitIter<'a> {
@@ -3,11 +3,14 @@ pub mod key;
 pub mod tile;
 pub mod control;
 
+use std::io;
+use std::str::FromStr;
+
 use image::Pixel;
 use image::Rgba;
 use image::RgbaImage;
+use serde::Serialize;
 
-use crate::color::biome::Biome;
 use crate::color::ColorManager;
 use crate::color::BakedColorManager;
 use data::TILESIZE;
@@ -17,9 +20,61 @@ use tile::Tile;
 
 pub type GEResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+/// what the color of a column shows
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    /// block colors
+    Color,
+    /// height of the surface in gray, black at 0 and white at 255
+    Height,
+}
+
+impl FromStr for RenderMode {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "color" => Ok(RenderMode::Color),
+            "height" => Ok(RenderMode::Height),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported")),
+        }
+    }
+}
+
+/// relief drawn over the colors
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shading {
+    None,
+    /// brighter where the surface rises from north to south, darker where it falls
+    Slope,
+}
+
+impl FromStr for Shading {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Shading::None),
+            "slope" => Ok(Shading::Slope),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported")),
+        }
+    }
+}
+
+/// serialized with the names of the server query
+#[derive(Clone, Debug, Serialize)]
 pub struct RenderOptions {
     gamma: f32,
+    #[serde(rename = "light")]
     env_light: u8,
+    mode: RenderMode,
+    shading: Shading,
+    /// layers drawn under and over the surface
+    seafloor: bool,
+    transparent: bool,
+    foliage: bool,
 }
 
 impl Default for RenderOptions {
@@ -28,6 +83,11 @@ impl Default for RenderOptions {
         RenderOptions {
             gamma: 1.0,
             env_light: 15,
+            mode: RenderMode::Color,
+            shading: Shading::None,
+            seafloor: true,
+            transparent: true,
+            foliage: true,
         }
     }
 }
@@ -43,6 +103,26 @@ impl RenderOptions {
     pub fn set_env_light(&mut self, light: u8) {
         self.env_light = std::cmp::min(light, 15);
     }
+
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_shading(&mut self, shading: Shading) {
+        self.shading = shading;
+    }
+
+    pub fn set_seafloor(&mut self, show: bool) {
+        self.seafloor = show;
+    }
+
+    pub fn set_transparent(&mut self, show: bool) {
+        self.transparent = show;
+    }
+
+    pub fn set_foliage(&mut self, show: bool) {
+        self.foliage = show;
+    }
 }
 
 
@@ -58,6 +138,45 @@ pub fn gamma_correction(c: &mut Rgba<u8>, gamma: f32) {
     c[2] = (b * 255.0) as u8;
 }
 
+/// `rise` is the height of the column minus the one north of it
+pub fn slope_shade(c: &mut Rgba<u8>, rise: i32) {
+    let factor = match rise {
+        r if r > 0 => 1.15,
+        r if r < 0 => 0.85,
+        _ => return,
+    };
+    for v in c.0.iter_mut().take(3) {
+        *v = (*v as f32 * factor).min(255.0) as u8;
+    }
+}
+
+/// gray of the surface height for `RenderMode::Height`, transparent where there is no block
+pub fn height_color(height: u8) -> Rgba<u8> {
+    if height > 0 {
+        Rgba::from([height, height, height, 255])
+    } else {
+        Rgba::from([0, 0, 0, 0])
+    }
+}
+
+/// `slope_shade` of the pixels by the rise of the surface from the north neighbour, `heights` row by row;
+/// the first row, whose north neighbour is in another tile, takes the rise to the south one, so tiles show no seam
+pub fn shade_slopes(panel: &mut RgbaImage, heights: &[u8]) {
+    let (width, height) = panel.dimensions();
+    if height < 2 {
+        return;
+    }
+    let at = |x: u32, z: u32| heights[(z * width + x) as usize];
+    for z in 0 .. height {
+        for x in 0 .. width {
+            let (from, to) = if z > 0 { (at(x, z - 1), at(x, z)) } else { (at(x, 0), at(x, 1)) };
+            if at(x, z) > 0 && from > 0 && to > 0 {
+                slope_shade(panel.get_pixel_mut(x, z), to as i32 - from as i32);
+            }
+        }
+    }
+}
+
 pub fn light_modify(c: &mut Rgba<u8>, light: u8) {
     let r = c[0] as u16;
     let g = c[1] as u16;
@@ -74,13 +193,18 @@ pub fn light_modify(c: &mut Rgba<u8>, light: u8) {
 
 pub fn render(tile: Tile, mgr: &BakedColorManager, options: &RenderOptions) -> RgbaImage {
     let mut panel = RgbaImage::new(TILESIZE.0, TILESIZE.1);
+    let mut heights = vec![0; (TILESIZE.0 * TILESIZE.1) as usize];
     let boxed_view = tile.view();
     let view = boxed_view.as_ref();
     for x in 0 .. TILESIZE.0 {
         for z in 0 .. TILESIZE.1 {
 
             let element = view.element(x, z);
-            let biome = Biome(view.biome(element) as usize);
+            if options.mode == RenderMode::Height {
+                panel.put_pixel(x, z, height_color(view.height(view.surface(element))));
+                continue;
+            }
+            let biome = tile.get_biome(view.biome(element));
             let mut surface = {
                 let layer = view.surface(element);
                 if view.height(layer) > 0 {
@@ -96,7 +220,7 @@ pub fn render(tile: Tile, mgr: &BakedColorManager, options: &RenderOptions) -> R
             let color = if surface.1 > 0 {
                 let mut seafloor = {
                     let layer = view.seafloor(element);
-                    if view.height(layer) > 0 {
+                    if options.seafloor && view.height(layer) > 0 {
                         let (c, props) = tile.get_color(view.blockstate_id(layer)); 
                         let mut c = mgr.get_modified_color(c.clone(), &props.biome_color, view.height(layer) as i32, &biome, props.waterlogged);
                         let light = std::cmp::max(view.blocklight(layer), options.env_light);
@@ -108,7 +232,7 @@ pub fn render(tile: Tile, mgr: &BakedColorManager, options: &RenderOptions) -> R
                 };
                 let mut transparent = {
                     let layer = view.transparent(element);
-                    if view.height(layer) > 0 {
+                    if options.transparent && view.height(layer) > 0 {
                         let (c, props) = tile.get_color(view.blockstate_id(layer)); 
                         let mut c = mgr.get_modified_color(c.clone(), &props.biome_color, view.height(layer) as i32, &biome, props.waterlogged);
                         let light = std::cmp::max(view.blocklight(layer), options.env_light);
@@ -120,7 +244,7 @@ pub fn render(tile: Tile, mgr: &BakedColorManager, options: &RenderOptions) -> R
                 };
                 let mut foliage = {
                     let layer = view.foliage(element);
-                    if view.height(layer) > 0 {
+                    if options.foliage && view.height(layer) > 0 {
                         let (c, props) = tile.get_color(view.blockstate_id(layer)); 
                         let mut c = mgr.get_modified_color(c.clone(), &props.biome_color, view.height(layer) as i32, &biome, props.waterlogged);
                         let light = std::cmp::max(view.blocklight(layer), options.env_light);
@@ -155,6 +279,7 @@ pub fn render(tile: Tile, mgr: &BakedColorManager, options: &RenderOptions) -> R
                 if transparent.1 > 0 && transparent.1 > surface.1 {
                     blend(&mut color, &transparent.0);
                 }
+                heights[(z * TILESIZE.0 + x) as usize] = surface.1;
                 color
             } else {
                 Rgba::from([0, 0, 0, 0])
@@ -163,9 +288,46 @@ pub fn render(tile: Tile, mgr: &BakedColorManager, options: &RenderOptions) -> R
             panel.put_pixel(x, z, color);
         }
     }
+    if options.mode == RenderMode::Color && options.shading == Shading::Slope {
+        shade_slopes(&mut panel, heights.as_slice());
+    }
     panel
 }
 
 fn blend<P: Pixel>(bg: &mut P, fg: &P) {
     bg.blend(fg);
-}
\ No newline at end of file
+}
+
+
+mod test {
+
+    #[test]
+    fn test_height_color() {
+        use super::height_color;
+
+        assert_eq!(height_color(0).0, [0, 0, 0, 0]);
+        assert_eq!(height_color(64).0, [64, 64, 64, 255]);
+    }
+
+    #[test]
+    fn test_shade_slopes() {
+        use image::Rgba;
+        use image::RgbaImage;
+        use super::shade_slopes;
+
+        // rising to the south in the first column, flat in the second, nothing in the third
+        let heights = [
+            10, 10, 0,
+            11, 10, 0,
+            12, 10, 0,
+        ];
+        let mut panel = RgbaImage::from_pixel(3, 3, Rgba::from([100, 100, 100, 255]));
+        shade_slopes(&mut panel, &heights);
+        for z in 0 .. 3 {
+            assert_eq!(panel.get_pixel(0, z).0, [115, 115, 115, 255], "row {}", z);
+            assert_eq!(panel.get_pixel(1, z).0, [100, 100, 100, 255]);
+            assert_eq!(panel.get_pixel(2, z).0, [100, 100, 100, 255]);
+        }
+    }
+
+}
@@ -1,6 +1,7 @@
 use std::io::Read;
 use std::io::Seek;
 use std::convert::TryFrom;
+use std::collections::HashMap;
 
 use log;
 use zip::ZipArchive;
@@ -8,6 +9,7 @@ use image::Rgba;
 
 use crate::color::ColorManager;
 use crate::color::BakedColorManager;
+use crate::color::biome::Biome;
 use super::data::TILESIZE;
 use super::data::View;
 use super::data::V1TileView;
@@ -20,8 +22,29 @@ use super::key::SplitIter;
 use super::control::Control;
 
 
+/// most bytes read from `key`, `biomes` or `control`, so that a small cache cannot unpack to gigabytes
+const MAX_TEXT_SIZE: u64 = 16 * 1024 * 1024;
+
 pub type GEResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+/// newer caches name their biomes: `<id> <namespace:name>` per line; unknown names are left out
+fn parse_biomes(s: &str, find: impl Fn(&str) -> Option<Biome>) -> HashMap<u16, Biome> {
+    let mut biomes = HashMap::new();
+    for line in s.lines() {
+        let mut sp = line.splitn(2, ' ');
+        match (sp.next().and_then(|id| id.parse().ok()), sp.next()) {
+            (Some(id), Some(name)) => match find(name.trim()) {
+                Some(biome) => {
+                    biomes.insert(id, biome);
+                }
+                None => log::warn!("unknown biome: `{}`", name),
+            },
+            _ => log::warn!("parse error: `{}`", line),
+        }
+    }
+    biomes
+}
+
 pub struct Tile {
 
     id: (i32, i32),
@@ -30,6 +53,8 @@ pub struct Tile {
 
     key: Vec<(Rgba<u8>, BlockProps)>,
 
+    biomes: HashMap<u16, Biome>,
+
     control: Control,
 }
 
@@ -39,18 +64,19 @@ impl Tile {
         let mut zip = ZipArchive::new(reader).map_err(Box::new)?;
         
         let mut data = Vec::new();
-        let n = zip.by_name("data").map_err(Box::new)?.read_to_end(&mut data).map_err(Box::new)?;
-        if n != TILESIZE.0 as usize * TILESIZE.1 as usize * 18 {
+        let size = TILESIZE.0 as usize * TILESIZE.1 as usize * 18;
+        let n = zip.by_name("data").map_err(Box::new)?.take(size as u64 + 1).read_to_end(&mut data).map_err(Box::new)?;
+        if n != size {
             return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "data")))
         }
         
         let mut key = Vec::new();
         let mut key_string = String::new();
-        let n = zip.by_name("key").map_err(Box::new)?.read_to_string(&mut key_string).map_err(Box::new)?;
+        let n = zip.by_name("key").map_err(Box::new)?.take(MAX_TEXT_SIZE).read_to_string(&mut key_string).map_err(Box::new)?;
         for line in key_string.lines() {
             match KeyLine::try_from(line) {
                 Ok(k) => {
-                    let props = BlockProps::new_from(k.name, SplitIter::from(k.state));
+                    let props = BlockProps::new_from(k.name, SplitIter::from(k.state), mgr.get_tint(k.name));
                     let model = mgr.get_basic_color(k.name, SplitIter::from(k.state), props.waterlogged);
                     
                     key.push((model, props));
@@ -62,8 +88,18 @@ impl Tile {
             }
         }
 
+        let mut biomes = HashMap::new();
+        if let Ok(ifile) = zip.by_name("biomes") {
+            let mut ifile = ifile.take(MAX_TEXT_SIZE);
+            let mut s = String::new();
+            if ifile.read_to_string(&mut s).is_ok() {
+                biomes = parse_biomes(s.as_str(), |name| mgr.find_biome(name));
+            }
+        }
+
         let mut control = Control::default();
-        if let Ok(mut ifile) = zip.by_name("control") {
+        if let Ok(ifile) = zip.by_name("control") {
+            let mut ifile = ifile.take(MAX_TEXT_SIZE);
             let mut s = String::new();
             if let Ok(n) = ifile.read_to_string(&mut s) {
                 for line in s.lines() {
@@ -79,6 +115,7 @@ impl Tile {
             id,
             data,
             key,
+            biomes,
             control
         })
     }
@@ -94,7 +131,33 @@ impl Tile {
         }   
     }
 
+    /// biome of the raw id in data, translated by the `biomes` entry if present
+    pub fn get_biome(&self, id: u16) -> Biome {
+        self.biomes.get(&id).cloned().unwrap_or(Biome(id as usize))
+    }
+
     pub fn get_color(&self, id: u16) -> &(Rgba<u8>, BlockProps) {
         &self.key[(id - 1) as usize]
     }
-}
\ No newline at end of file
+}
+
+
+mod test {
+
+    #[test]
+    fn test_parse_biomes() {
+        use super::parse_biomes;
+        use crate::color::biome::Biome;
+
+        let find = |name: &str| match name {
+            "minecraft:plains" => Some(Biome(1)),
+            "minecraft:cherry_grove" => Some(Biome(185)),
+            _ => None,
+        };
+        let biomes = parse_biomes("0 minecraft:plains\n3 minecraft:cherry_grove \nx minecraft:plains\n4 mod:unknown\n5\n", find);
+        let mut ids: Vec<_> = biomes.iter().map(|(id, biome)| (*id, biome.0)).collect();
+        ids.sort();
+        assert_eq!(ids, vec![(0, 1), (3, 185)]);
+    }
+
+}
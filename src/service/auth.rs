@@ -0,0 +1,332 @@
+use std::io;
+use std::path::Path;
+use std::fs::File;
+use std::io::BufReader;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::http::header;
+use serde::Deserialize;
+use hmac::Hmac;
+use hmac::Mac;
+use hmac::NewMac;
+use sha2::Digest;
+use sha2::Sha256;
+
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// the scope a token only has if it lists it
+const ADMIN: &str = "admin";
+
+/// limits of a token; `None` is unlimited
+#[derive(Deserialize, Clone, Default)]
+pub struct Quota {
+    /// requests per minute
+    #[serde(default)]
+    rate: Option<u32>,
+    /// tasks running at the same time, jobs count until they finish
+    #[serde(default)]
+    tasks: Option<usize>,
+    /// endpoints allowed: "render", "jobs", "upload", "admin"; all but "admin" if missing
+    #[serde(default)]
+    scopes: Option<Vec<String>>,
+}
+
+impl Quota {
+
+    fn allows(&self, scope: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|s| s == scope),
+            None => scope != ADMIN,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StaticToken {
+    id: String,
+    token: String,
+    #[serde(flatten)]
+    quota: Quota,
+}
+
+/// tokens `<id>.<expire unix time>.<hex of hmac-sha256 of "<id>.<expire>">`
+#[derive(Deserialize)]
+struct HmacConfig {
+    key: String,
+    #[serde(flatten)]
+    quota: Quota,
+}
+
+#[derive(Deserialize)]
+struct AuthConfig {
+    #[serde(default)]
+    tokens: Vec<StaticToken>,
+    #[serde(default)]
+    hmac: Option<HmacConfig>,
+}
+
+struct Usage {
+    window: Instant,
+    requests: u32,
+    tasks: Arc<AtomicUsize>,
+}
+
+/// why a request is refused
+#[derive(Debug, PartialEq)]
+pub enum Denied {
+    /// no token, unknown, badly signed or expired
+    Unauthorized,
+    /// valid token without the scope
+    Forbidden,
+    /// rate or task quota reached
+    TooManyRequests,
+}
+
+impl Denied {
+
+    fn reason(&self) -> &'static str {
+        match self {
+            Denied::Unauthorized => "unauthorized",
+            Denied::Forbidden => "forbidden",
+            Denied::TooManyRequests => "too many requests",
+        }
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            Denied::Unauthorized => {
+                let mut builder = HttpResponse::Unauthorized();
+                builder.header(header::WWW_AUTHENTICATE, "Bearer");
+                builder.finish()
+            }
+            Denied::Forbidden => HttpResponse::Forbidden().finish(),
+            Denied::TooManyRequests => HttpResponse::TooManyRequests().finish(),
+        }
+    }
+}
+
+/// a checked token, holding one of its tasks until dropped if counted as a task
+pub struct Permit {
+    id: String,
+    tasks: Option<Arc<AtomicUsize>>,
+}
+
+impl Permit {
+
+    pub fn id(&self) -> &str {
+        self.id.as_str()
+    }
+}
+
+impl Drop for Permit {
+
+    fn drop(&mut self) {
+        if let Some(tasks) = &self.tasks {
+            tasks.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0 .. s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i .. i + 2)?, 16).ok()).collect()
+}
+
+/// sha-256 of a static token; tokens are compared by digest, so in a time that does not depend on the token
+fn digest(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// whether `a` and `b` are equal, looking at every byte
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+/**
+ * bearer tokens of the config file given by `--auth`, static or hmac-signed,
+ * each with its quota counted by token id
+ */
+pub struct Auth {
+    /// digest of the token, id and quota
+    tokens: Vec<(Vec<u8>, String, Quota)>,
+    hmac: Option<(Vec<u8>, Quota)>,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl Auth {
+
+    pub fn load(file: &Path) -> io::Result<Self> {
+        let config: AuthConfig = serde_json::from_reader(BufReader::new(File::open(file)?))?;
+        Ok(Self::new(config))
+    }
+
+    fn new(config: AuthConfig) -> Self {
+        let tokens = config.tokens.into_iter().map(|t| (digest(t.token.as_str()), t.id, t.quota)).collect();
+        Auth {
+            tokens,
+            hmac: config.hmac.map(|h| (h.key.into_bytes(), h.quota)),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// id and quota of a valid token at unix time `now`; every static token is compared
+    fn identify(&self, token: &str, now: u64) -> Option<(String, Quota)> {
+        let token_digest = digest(token);
+        let found = self.tokens.iter().fold(None, |found, t| {
+            if constant_eq(t.0.as_slice(), token_digest.as_slice()) { Some(t) } else { found }
+        });
+        if let Some((_, id, quota)) = found {
+            return Some((id.clone(), quota.clone()));
+        }
+        let (key, quota) = self.hmac.as_ref()?;
+        let (payload, signature) = token.rsplit_once('.')?;
+        let (id, expire) = payload.rsplit_once('.')?;
+        let expire: u64 = expire.parse().ok()?;
+        if id.is_empty() || expire < now {
+            return None;
+        }
+        let mut mac = Hmac::<Sha256>::new_varkey(key.as_slice()).ok()?;
+        mac.update(payload.as_bytes());
+        mac.verify(from_hex(signature)?.as_slice()).ok()?;
+        Some((String::from(id), quota.clone()))
+    }
+
+    /// the bearer token of the request allowed for `scope`, counted as one request, and one task if `task`
+    pub fn check(&self, req: &HttpRequest, scope: &str, task: bool) -> Result<Permit, Denied> {
+        let token = req.headers().get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        match self.admit(token, scope, task, now, Instant::now()) {
+            Ok(permit) => {
+                log::info!("[{}] {} {}", permit.id, req.method(), req.path());
+                Ok(permit)
+            }
+            Err((id, denied)) => {
+                log::warn!("[{}] {} {}: {}", id, req.method(), req.path(), denied.reason());
+                Err(denied)
+            }
+        }
+    }
+
+    /// `check` of a token at unix time `now` and instant `instant`; the id is "-" for an invalid token
+    fn admit(&self, token: Option<&str>, scope: &str, task: bool, now: u64, instant: Instant) -> Result<Permit, (String, Denied)> {
+        let (id, quota) = match token.and_then(|token| self.identify(token, now)) {
+            Some(v) => v,
+            None => return Err((String::from("-"), Denied::Unauthorized)),
+        };
+        if !quota.allows(scope) {
+            return Err((id, Denied::Forbidden));
+        }
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(id.clone()).or_insert_with(|| Usage {
+            window: instant,
+            requests: 0,
+            tasks: Arc::new(AtomicUsize::new(0)),
+        });
+        if instant.saturating_duration_since(usage.window) >= WINDOW {
+            usage.window = instant;
+            usage.requests = 0;
+        }
+        let over_rate = quota.rate.map(|rate| usage.requests >= rate).unwrap_or(false);
+        let over_tasks = task && quota.tasks.map(|tasks| usage.tasks.load(Ordering::SeqCst) >= tasks).unwrap_or(false);
+        if over_rate || over_tasks {
+            return Err((id, Denied::TooManyRequests));
+        }
+        usage.requests += 1;
+        let tasks = if task {
+            usage.tasks.fetch_add(1, Ordering::SeqCst);
+            Some(usage.tasks.clone())
+        } else {
+            None
+        };
+        Ok(Permit {
+            id,
+            tasks,
+        })
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_scopes() {
+        use super::Auth;
+        use super::Denied;
+        use std::time::Instant;
+
+        let config = serde_json::from_str(r#"{"tokens": [
+            { "id": "site", "token": "secret" },
+            { "id": "ops", "token": "root", "scopes": ["admin"] }
+        ]}"#).unwrap();
+        let auth = Auth::new(config);
+        let now = Instant::now();
+        assert_eq!(auth.admit(Some("secret"), "render", false, 0, now).ok().unwrap().id(), "site");
+        assert_eq!(auth.admit(Some("secret"), "admin", false, 0, now).err().unwrap().1, Denied::Forbidden);
+        assert!(auth.admit(Some("root"), "admin", false, 0, now).is_ok());
+        assert_eq!(auth.admit(Some("root"), "render", false, 0, now).err().unwrap().1, Denied::Forbidden);
+        assert_eq!(auth.admit(Some("secreT"), "render", false, 0, now).err().unwrap().1, Denied::Unauthorized);
+        assert_eq!(auth.admit(None, "render", false, 0, now).err().unwrap().1, Denied::Unauthorized);
+    }
+
+    #[test]
+    fn test_hmac_token() {
+        use super::Auth;
+        use hmac::Hmac;
+        use hmac::Mac;
+        use hmac::NewMac;
+        use sha2::Sha256;
+
+        let auth = Auth::new(serde_json::from_str(r#"{"hmac": { "key": "k" }}"#).unwrap());
+        let sign = |payload: &str| {
+            let mut mac = Hmac::<Sha256>::new_varkey(b"k").unwrap();
+            mac.update(payload.as_bytes());
+            let signature: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{}.{}", payload, signature)
+        };
+        assert_eq!(auth.identify(sign("bot.100").as_str(), 100).map(|(id, _)| id), Some(String::from("bot")));
+        // expired
+        assert!(auth.identify(sign("bot.100").as_str(), 101).is_none());
+        // signature of another payload
+        let forged = sign("bot.100").replacen("bot.100", "bot.200", 1);
+        assert!(auth.identify(forged.as_str(), 100).is_none());
+        assert!(auth.identify("bot.100.zz", 100).is_none());
+    }
+
+    #[test]
+    fn test_quota() {
+        use super::Auth;
+        use super::Denied;
+        use super::WINDOW;
+        use std::time::Duration;
+        use std::time::Instant;
+
+        let auth = Auth::new(serde_json::from_str(r#"{"tokens": [{ "id": "a", "token": "t", "rate": 2, "tasks": 1 }]}"#).unwrap());
+        let start = Instant::now();
+        let permit = auth.admit(Some("t"), "jobs", true, 0, start).ok().unwrap();
+        // one task at a time
+        assert_eq!(auth.admit(Some("t"), "jobs", true, 0, start).err().unwrap().1, Denied::TooManyRequests);
+        drop(permit);
+        assert!(auth.admit(Some("t"), "jobs", true, 0, start).is_ok());
+        // 2 requests counted in this window
+        assert_eq!(auth.admit(Some("t"), "render", false, 0, start + Duration::from_secs(59)).err().unwrap().1, Denied::TooManyRequests);
+        assert!(auth.admit(Some("t"), "render", false, 0, start + WINDOW).is_ok());
+    }
+
+}
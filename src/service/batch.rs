@@ -0,0 +1,411 @@
+use std::io;
+use std::fmt;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::sync::atomic::AtomicUsize;
+use std::thread;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::time::Instant;
+
+use bytes::Bytes;
+use bytes::BytesMut;
+use bytes::BufMut;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Error as ActixError;
+use actix_web::error::BlockingError;
+use actix_web::http::header;
+use actix_multipart::Multipart;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use serde::Serialize;
+use image::RgbaImage;
+use image::imageops::FilterType;
+use zip::ZipArchive;
+use zip::ZipWriter;
+use zip::CompressionMethod;
+use zip::write::FileOptions;
+use zip::result::ZipError;
+
+use super::RenderService;
+use super::authorize;
+use super::colors;
+use super::MAX_TILE_SIZE;
+use super::parse_tile_id;
+use super::options;
+use super::metrics::Metrics;
+use super::metrics::decode_cause;
+use super::resources::panic_message;
+use crate::render;
+use crate::render::RenderOptions;
+use crate::render::tile::Tile;
+use crate::color::BakedColorManager;
+use crate::encoder::TileEncoder;
+use crate::tilegen;
+use crate::tilegen::PathMode;
+use crate::tilegen::store::MemoryStore;
+use crate::tilegen::store::TileStore;
+
+
+pub const MAX_BATCH_SIZE: usize = 64 * 1024 * 1024;
+
+/// most entries in an archive, directories included
+pub const MAX_BATCH_ENTRIES: usize = 4096;
+
+/// most bytes of caches unpacked from an archive, all kept in memory until rendered
+pub const MAX_BATCH_UNPACKED: usize = 256 * 1024 * 1024;
+
+/// what `/render/batch` returns, from the multipart field `output`
+#[derive(Clone, Copy, PartialEq)]
+pub enum BatchOutput {
+    /// `x,z.png` for each cache
+    Png,
+    /// tiles of path mode "xyz" with `tiles.json` and `index.html`
+    Pyramid,
+}
+
+#[derive(Serialize)]
+pub struct TileError {
+    pub file: String,
+    pub error: String,
+}
+
+/// why a whole archive is refused
+#[derive(Debug)]
+pub enum BatchError {
+    /// more entries or unpacked bytes than allowed, 413
+    TooLarge(String),
+    /// an invalid archive or a failure to write the result, 400
+    Failed(String),
+}
+
+impl BatchError {
+
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            BatchError::TooLarge(e) => HttpResponse::PayloadTooLarge().body(e.clone()),
+            BatchError::Failed(e) => HttpResponse::BadRequest().body(e.clone()),
+        }
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BatchError::TooLarge(e) | BatchError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for BatchError {
+    fn from(e: io::Error) -> Self {
+        BatchError::Failed(e.to_string())
+    }
+}
+
+impl From<ZipError> for BatchError {
+    fn from(e: ZipError) -> Self {
+        BatchError::Failed(e.to_string())
+    }
+}
+
+fn too_many_entries() -> BatchError {
+    BatchError::TooLarge(format!("more than {} entries in the archive", MAX_BATCH_ENTRIES))
+}
+
+/// number of entries given by the end of central directory record, to refuse an archive before it is opened;
+/// a zip64 archive gives at least `0xFFFF`
+fn entry_count(data: &[u8]) -> Option<usize> {
+    let last = data.len().checked_sub(22)?;
+    let first = last.saturating_sub(u16::MAX as usize);
+    (first ..= last).rev()
+        .find(|&i| data[i .. i + 4] == [0x50, 0x4b, 0x05, 0x06])
+        .map(|i| u16::from_le_bytes([data[i + 10], data[i + 11]]) as usize)
+}
+
+/// `manifest.json` in the returned archive
+#[derive(Serialize, Default)]
+struct Manifest {
+    rendered: Vec<String>,
+    errors: Vec<TileError>,
+    tiles: usize,
+}
+
+
+/// failures of decoding are counted in `metrics` by cause; a cache whose data does not match its key
+/// makes the renderer panic, which is returned as an error of cause "bad_data"
+pub fn render_picture(data: Vec<u8>, tile_id: (i32, i32), mgr: &BakedColorManager, options: &RenderOptions, metrics: &Metrics) -> Result<RgbaImage, String> {
+    let tile = Tile::load(Cursor::new(data), tile_id, mgr).map_err(|e| {
+        metrics.decode_failure(decode_cause(e.as_ref()));
+        e.to_string()
+    })?;
+    panic::catch_unwind(AssertUnwindSafe(|| render::render(tile, mgr, options))).map_err(|e| {
+        metrics.decode_failure("bad_data");
+        format!("invalid cache: {}", panic_message(e))
+    })
+}
+
+fn render_cache(data: Vec<u8>, tile_id: (i32, i32), mgr: &BakedColorManager, options: &RenderOptions, metrics: &Metrics) -> Result<Vec<u8>, String> {
+    let pic = render_picture(data, tile_id, mgr, options, metrics)?;
+    TileEncoder::Png.encode(&pic).map_err(|e| e.to_string())
+}
+
+/// caches `x,z.zip` anywhere in the archive; other files are ignored.
+/// an entry is read up to `MAX_TILE_SIZE` whatever its header claims
+fn read_caches(data: Bytes, manifest: &mut Manifest) -> Result<Vec<(String, (i32, i32), Vec<u8>)>, BatchError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    if archive.len() > MAX_BATCH_ENTRIES {
+        return Err(too_many_entries());
+    }
+    let mut caches = Vec::new();
+    let mut seen = HashSet::new();
+    let mut unpacked = 0;
+    for i in 0 .. archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = String::from(file.name());
+        let name = path.rsplit('/').next().unwrap_or_default();
+        if let Some(tile_id) = parse_tile_id(name) {
+            if !seen.insert(tile_id) {
+                manifest.errors.push(TileError { file: path, error: String::from("duplicate tile") });
+            } else if file.size() as usize > MAX_TILE_SIZE {
+                manifest.errors.push(TileError { file: path, error: String::from("too large") });
+            } else {
+                let mut buf = Vec::with_capacity(file.size() as usize);
+                (&mut file).take(MAX_TILE_SIZE as u64 + 1).read_to_end(&mut buf)?;
+                if buf.len() > MAX_TILE_SIZE {
+                    manifest.errors.push(TileError { file: path, error: String::from("too large") });
+                    continue;
+                }
+                unpacked += buf.len();
+                if unpacked > MAX_BATCH_UNPACKED {
+                    return Err(BatchError::TooLarge(format!("more than {} MiB of caches in the archive", MAX_BATCH_UNPACKED / 1024 / 1024)));
+                }
+                caches.push((path, tile_id, buf));
+            }
+        }
+    }
+    Ok(caches)
+}
+
+fn write_archive(store: &MemoryStore, manifest: &Manifest) -> io::Result<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for key in store.keys() {
+        if let Some(data) = store.get(key.as_str())? {
+            writer.start_file(key.as_str(), stored)?;
+            writer.write_all(data.as_slice())?;
+        }
+    }
+    writer.start_file("manifest.json", FileOptions::default())?;
+    serde_json::to_writer_pretty(&mut writer, manifest)?;
+    Ok(writer.finish()?.into_inner())
+}
+
+/// renders the caches of the archive with `workers` threads, errors of single caches are listed in `manifest.json`;
+/// `progress` is called with the number of caches finished and the total
+pub fn render_archive(data: Bytes, mgr: &BakedColorManager, options: &RenderOptions, output: BatchOutput, workers: usize, progress: &(dyn Fn(usize, usize) + Sync), metrics: &Metrics) -> Result<Bytes, BatchError> {
+    let mut manifest = Manifest::default();
+    let caches = read_caches(data, &mut manifest)?;
+    let total = caches.len();
+    let finished = AtomicUsize::new(0);
+    progress(0, total);
+    let queue = Mutex::new(caches);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0 .. workers.max(1) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().pop();
+                let (path, tile_id, data) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                let r = render_cache(data, tile_id, mgr, options, metrics);
+                results.lock().unwrap().push((path, tile_id, r));
+                progress(finished.fetch_add(1, Ordering::SeqCst) + 1, total);
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let rendered = MemoryStore::new();
+    for (path, (x, z), r) in results {
+        match r {
+            Ok(png) => {
+                rendered.put(format!("{},{}.png", x, z).as_str(), png)?;
+                manifest.rendered.push(path);
+            }
+            Err(e) => {
+                log::warn!("batch render {} fail: {}", path, e);
+                manifest.errors.push(TileError { file: path, error: e });
+            }
+        }
+    }
+    let store = match output {
+        BatchOutput::Png => {
+            manifest.tiles = manifest.rendered.len();
+            rendered
+        }
+        BatchOutput::Pyramid => {
+            let pyramid = MemoryStore::new();
+            let tiles = tilegen::list_tiles(&rendered);
+            manifest.tiles = tilegen::generate_into(tiles, &rendered, &pyramid, &PathMode::Xyz { max_zoom: None }, TileEncoder::Png, FilterType::Nearest);
+            pyramid
+        }
+    };
+    let buf = write_archive(&store, &manifest)?;
+    Ok(Bytes::from(buf))
+}
+
+
+/// multipart with a zip of caches `x,z.zip`, an optional text field `output`, "png" (default) or "pyramid",
+/// and an optional json field `options` read over `render_options`; the response to send instead if the upload is invalid,
+/// 413 for an archive with more than `MAX_BATCH_ENTRIES` entries
+pub async fn read_upload(payload: &mut Multipart, render_options: &mut RenderOptions) -> Result<(Bytes, BatchOutput), HttpResponse> {
+    let mut archive = None;
+    let mut output = BatchOutput::Png;
+    while let Some(mut field) = payload.try_next().await.map_err(|e| HttpResponse::from_error(e.into()))? {
+        let name = field.content_disposition()
+            .and_then(|cd| cd.get_name().map(String::from))
+            .unwrap_or_default();
+        if name == options::FIELD {
+            options::read_field(render_options, &mut field).await?;
+            continue;
+        }
+        let mut buf = BytesMut::new();
+        while let Some(chunk) = field.next().await {
+            let data = chunk.map_err(|e| HttpResponse::from_error(e.into()))?;
+            if data.len() + buf.len() > MAX_BATCH_SIZE {
+                return Err(
+                    HttpResponse::PayloadTooLarge()
+                        .into()
+                );
+            }
+            buf.put(data);
+        }
+        if name == "output" {
+            output = match std::str::from_utf8(&buf) {
+                Ok("png") => BatchOutput::Png,
+                Ok("pyramid") => BatchOutput::Pyramid,
+                _ => {
+                    return Err(
+                        HttpResponse::BadRequest()
+                            .body("output should be \"png\" or \"pyramid\"")
+                            .into()
+                    );
+                }
+            };
+        } else if archive.is_none() {
+            if entry_count(&buf).map(|n| n > MAX_BATCH_ENTRIES).unwrap_or(false) {
+                return Err(too_many_entries().response());
+            }
+            archive = Some(buf.freeze());
+        }
+    }
+    match archive {
+        Some(archive) => Ok((archive, output)),
+        None => Err(HttpResponse::NotFound().into()),
+    }
+}
+
+pub fn archive_response(buf: Bytes) -> HttpResponse {
+    let mut builder = HttpResponse::Ok();
+    builder.content_type("application/zip");
+    builder.set(header::ContentDisposition {
+        disposition: header::DispositionType::Attachment,
+        parameters: vec![
+            header::DispositionParam::Filename(String::from("tiles.zip"))
+        ]
+    });
+    builder.body(buf)
+}
+
+
+/// returns a zip of the pictures or tiles and `manifest.json`
+pub async fn render_batch(s: web::Data<RenderService>, req: HttpRequest, mut payload: Multipart) -> Result<HttpResponse, ActixError> {
+
+    let _permit = match authorize(&s, &req, "render", true) {
+        Ok(permit) => permit,
+        Err(response) => return Ok(response),
+    };
+    let mgr = match colors(&s, &req) {
+        Ok(mgr) => mgr,
+        Err(response) => return Ok(response),
+    };
+
+    if s.working.load(Ordering::SeqCst) >= s.options.max_tasks {
+        return Ok(HttpResponse::TooManyRequests().into())
+    }
+
+    let mut render_options = match options::from_query(req.query_string()) {
+        Ok(render_options) => render_options,
+        Err(e) => return Ok(e.response()),
+    };
+    let (archive, output) = match read_upload(&mut payload, &mut render_options).await {
+        Ok(upload) => upload,
+        Err(response) => return Ok(response),
+    };
+
+    s.working.fetch_add(1, Ordering::SeqCst);
+    s.metrics.payload("batch", archive.len());
+
+    let metrics = s.metrics.clone();
+    let workers = s.options.workers;
+    let time = Instant::now();
+    let r = web::block(move || render_archive(archive, &mgr, &render_options, output, workers, &|_, _| { }, &metrics)).await;
+
+    s.working.fetch_sub(1, Ordering::SeqCst);
+    s.metrics.render_time("batch", time.elapsed().as_secs_f64());
+
+    match r {
+        Ok(buf) => {
+            Ok(archive_response(buf))
+        }
+        Err(BlockingError::Error(e)) => Ok(e.response()),
+        Err(e) => {
+            Ok(
+                HttpResponse::BadRequest()
+                    .body(e.to_string())
+                    .into()
+            )
+        }
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_read_caches() {
+        use std::io::Cursor;
+        use std::io::Write;
+        use zip::ZipWriter;
+        use zip::write::FileOptions;
+        use super::Manifest;
+        use super::read_caches;
+        use super::entry_count;
+        use super::MAX_TILE_SIZE;
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("world/0,0.zip", FileOptions::default()).unwrap();
+        writer.write_all(b"cache").unwrap();
+        writer.start_file("1,0.zip", FileOptions::default()).unwrap();
+        writer.write_all(vec![0; MAX_TILE_SIZE + 1].as_slice()).unwrap();
+        writer.start_file("readme.txt", FileOptions::default()).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(entry_count(data.as_slice()), Some(3));
+        assert_eq!(entry_count(b"not a zip"), None);
+
+        let mut manifest = Manifest::default();
+        let caches = read_caches(data.into(), &mut manifest).ok().unwrap();
+        assert_eq!(caches, vec![(String::from("world/0,0.zip"), (0, 0), b"cache".to_vec())]);
+        assert_eq!(manifest.errors.iter().map(|e| e.file.as_str()).collect::<Vec<_>>(), vec!["1,0.zip"]);
+    }
+
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::render::RenderOptions;
+
+
+const EXT: &str = ".png";
+
+/// a picture being written, renamed to `<key>.png` once complete
+const TMP_EXT: &str = ".tmp";
+
+/// hex sha-256 of everything a rendered picture depends on; `resources` is the fingerprint of the profile
+pub fn key(data: &[u8], tile_id: (i32, i32), options: &RenderOptions, profile: &str, resources: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.update(format!("\n{},{}\n", tile_id.0, tile_id.1).as_bytes());
+    hasher.update(serde_json::to_string(options).unwrap_or_default().as_bytes());
+    hasher.update(format!("\n{}\n{}", profile, resources).as_bytes());
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn is_key(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+
+struct Entry<V> {
+    value: V,
+    size: usize,
+    used: u64,
+}
+
+/// values by key up to `limit` bytes, the least recently used dropped first
+struct Lru<V> {
+    limit: usize,
+    size: usize,
+    tick: u64,
+    entries: HashMap<String, Entry<V>>,
+    order: BTreeMap<u64, String>,
+}
+
+impl<V: Clone> Lru<V> {
+
+    fn new(limit: usize) -> Self {
+        Lru {
+            limit,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.used);
+        self.tick += 1;
+        entry.used = self.tick;
+        self.order.insert(self.tick, String::from(key));
+        Some(entry.value.clone())
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.used);
+            self.size -= entry.size;
+        }
+    }
+
+    /// the keys dropped to stay under the limit, with `key` itself if it is larger than the limit
+    fn insert(&mut self, key: String, value: V, size: usize) -> Vec<String> {
+        self.remove(key.as_str());
+        if size > self.limit {
+            return vec![key];
+        }
+        let mut dropped = Vec::new();
+        while self.size + size > self.limit {
+            let oldest = match self.order.keys().next() {
+                Some(used) => *used,
+                None => break,
+            };
+            let old_key = self.order.remove(&oldest).unwrap();
+            self.remove(old_key.as_str());
+            dropped.push(old_key);
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, Entry { value, size, used: self.tick });
+        self.size += size;
+        dropped
+    }
+}
+
+
+/**
+ * pictures rendered by `/render` by `key`: in memory up to `memory` bytes,
+ * and as `<key>.png` in a folder up to `disk` bytes, kept across restarts;
+ * a file is written under a temporary name first, so a crash never leaves a partial `<key>.png`
+ */
+pub struct RenderCache {
+    memory: Mutex<Lru<Bytes>>,
+    disk: Option<(PathBuf, Mutex<Lru<()>>)>,
+    writes: AtomicUsize,
+}
+
+impl RenderCache {
+
+    /// the pictures already in `dir` are used from oldest to newest, files left by an interrupted write are removed
+    pub fn new(memory: usize, disk: Option<(PathBuf, usize)>) -> Self {
+        let disk = disk.map(|(dir, limit)| {
+            if let Err(e) = fs::create_dir_all(dir.as_path()) {
+                log::warn!("fail to create render cache {}: {}", dir.display(), e);
+            }
+            let mut files: Vec<(SystemTime, String, usize)> = fs::read_dir(dir.as_path())
+                .map(|entries| {
+                    entries.filter_map(Result::ok)
+                        .filter_map(|entry| {
+                            let name = entry.file_name().to_string_lossy().into_owned();
+                            if name.ends_with(TMP_EXT) {
+                                let _ = fs::remove_file(entry.path());
+                                return None;
+                            }
+                            let key = name.strip_suffix(EXT).filter(|key| is_key(key))?;
+                            let meta = entry.metadata().ok()?;
+                            Some((meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), String::from(key), meta.len() as usize))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            files.sort();
+            let mut lru = Lru::new(limit);
+            for (_, key, size) in files {
+                for old in lru.insert(key, (), size) {
+                    let _ = fs::remove_file(dir.join(old + EXT));
+                }
+            }
+            log::info!("render cache {}: {} pictures, {} MB", dir.display(), lru.entries.len(), lru.size / 1024 / 1024);
+            (dir, Mutex::new(lru))
+        });
+        RenderCache {
+            memory: Mutex::new(Lru::new(memory)),
+            disk,
+            writes: AtomicUsize::new(0),
+        }
+    }
+
+    /// a picture found on disk is also put back in memory
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        if let Some(data) = self.memory.lock().unwrap().get(key) {
+            return Some(data);
+        }
+        let (dir, lru) = self.disk.as_ref()?;
+        lru.lock().unwrap().get(key)?;
+        match fs::read(dir.join(String::from(key) + EXT)) {
+            Ok(data) => {
+                let data = Bytes::from(data);
+                self.memory.lock().unwrap().insert(String::from(key), data.clone(), data.len());
+                Some(data)
+            }
+            Err(_) => {
+                lru.lock().unwrap().remove(key);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: &str, data: Bytes) {
+        self.memory.lock().unwrap().insert(String::from(key), data.clone(), data.len());
+        if let Some((dir, lru)) = &self.disk {
+            let tmp_file = dir.join(format!("{}.{}{}", key, self.writes.fetch_add(1, Ordering::SeqCst), TMP_EXT));
+            let written = fs::write(&tmp_file, &data).and_then(|_| fs::rename(&tmp_file, dir.join(String::from(key) + EXT)));
+            if let Err(e) = written {
+                log::warn!("fail to write render cache: {}", e);
+                let _ = fs::remove_file(&tmp_file);
+                return;
+            }
+            let dropped = lru.lock().unwrap().insert(String::from(key), (), data.len());
+            for old in dropped {
+                let _ = fs::remove_file(dir.join(old + EXT));
+            }
+        }
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_lru() {
+        use super::Lru;
+
+        let mut lru = Lru::new(10);
+        assert!(lru.insert(String::from("a"), 1, 4).is_empty());
+        assert!(lru.insert(String::from("b"), 2, 4).is_empty());
+        assert_eq!(lru.get("a"), Some(1));
+        assert_eq!(lru.insert(String::from("c"), 3, 4), vec![String::from("b")]);
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.insert(String::from("d"), 4, 11), vec![String::from("d")]);
+        assert_eq!(lru.size, 8);
+    }
+
+    #[test]
+    fn test_render_cache() {
+        use super::RenderCache;
+        use super::key;
+        use crate::render::RenderOptions;
+        use bytes::Bytes;
+
+        let dir = std::env::temp_dir().join(format!("render-cache-{}", std::process::id()));
+        let options = RenderOptions::default();
+        let k1 = key(b"zip", (0, 0), &options, "default", "fp");
+        assert_ne!(k1, key(b"zip", (0, 1), &options, "default", "fp"));
+        assert_ne!(k1, key(b"zip", (0, 0), &options, "default", "fp2"));
+
+        let cache = RenderCache::new(4, Some((dir.clone(), 100)));
+        std::fs::write(dir.join(format!("{}.png.0.tmp", k1)), b"partial").unwrap();
+        cache.put(k1.as_str(), Bytes::from(vec![7; 8]));
+        assert_eq!(cache.get(k1.as_str()), Some(Bytes::from(vec![7; 8])));
+        // not in memory, read again from disk after a restart
+        let cache = RenderCache::new(4, Some((dir.clone(), 100)));
+        assert_eq!(cache.get(k1.as_str()), Some(Bytes::from(vec![7; 8])));
+        assert_eq!(cache.get(key(b"other", (0, 0), &options, "default", "fp").as_str()), None);
+        assert_eq!(std::fs::read_dir(dir.as_path()).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+}
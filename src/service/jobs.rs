@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Condvar;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::thread::Builder;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Error as ActixError;
+use actix_multipart::Multipart;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+use super::RenderService;
+use super::authorize;
+use super::colors;
+use super::auth::Permit;
+use super::metrics::Metrics;
+use super::batch;
+use super::batch::BatchOutput;
+use super::options;
+use super::resources::panic_message;
+use crate::render::RenderOptions;
+use crate::color::BakedColorManager;
+
+
+/// most finished jobs kept, and most bytes of their archives; the oldest are dropped first, even before the retention
+const MAX_FINISHED_JOBS: usize = 256;
+const MAX_FINISHED_SIZE: usize = 1024 * 1024 * 1024;
+
+struct JobInput {
+    archive: Bytes,
+    options: RenderOptions,
+    output: BatchOutput,
+    mgr: Arc<BakedColorManager>,
+    threads: usize,
+    metrics: Arc<Metrics>,
+    /// the task of the token until the job finishes
+    permit: Option<Permit>,
+}
+
+enum JobState {
+    Queued,
+    Running,
+    Done(Bytes, Instant),
+    Failed(String, Instant),
+}
+
+/// one `/render/batch` run in the background
+pub struct Job {
+    id: String,
+    /// token id of the submitter
+    owner: Option<String>,
+    input: Mutex<Option<JobInput>>,
+    state: Mutex<JobState>,
+    finished: AtomicUsize,
+    total: AtomicUsize,
+}
+
+/// body of `GET /jobs/{id}`
+#[derive(Serialize)]
+pub struct JobStatus {
+    id: String,
+    state: &'static str,
+    finished: usize,
+    total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Job {
+
+    fn new(id: String, owner: Option<String>, input: Option<JobInput>) -> Self {
+        Job {
+            id,
+            owner,
+            input: Mutex::new(input),
+            state: Mutex::new(JobState::Queued),
+            finished: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+        }
+    }
+
+    /// whether the token `id` may see the job; `None` when auth is disabled
+    fn visible_to(&self, id: Option<&str>) -> bool {
+        match id {
+            Some(id) => self.owner.as_deref() == Some(id),
+            None => true,
+        }
+    }
+
+    pub fn status(&self) -> JobStatus {
+        let (state, error) = match &*self.state.lock().unwrap() {
+            JobState::Queued => ("queued", None),
+            JobState::Running => ("running", None),
+            JobState::Done(_, _) => ("done", None),
+            JobState::Failed(e, _) => ("failed", Some(e.clone())),
+        };
+        JobStatus {
+            id: self.id.clone(),
+            state,
+            finished: self.finished.load(Ordering::SeqCst),
+            total: self.total.load(Ordering::SeqCst),
+            error,
+        }
+    }
+
+    /// the archive once the job is done
+    pub fn result(&self) -> Option<Bytes> {
+        match &*self.state.lock().unwrap() {
+            JobState::Done(buf, _) => Some(buf.clone()),
+            _ => None,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), JobState::Running)
+    }
+
+    fn is_pending(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), JobState::Queued | JobState::Running)
+    }
+
+    fn expired(&self, retention: Duration) -> bool {
+        match &*self.state.lock().unwrap() {
+            JobState::Done(_, t) | JobState::Failed(_, t) => t.elapsed() > retention,
+            _ => false,
+        }
+    }
+
+    /// when the job finished and the size of its archive
+    fn finished_at(&self) -> Option<(Instant, usize)> {
+        match &*self.state.lock().unwrap() {
+            JobState::Done(buf, t) => Some((*t, buf.len())),
+            JobState::Failed(_, t) => Some((*t, 0)),
+            _ => None,
+        }
+    }
+
+    fn fail(&self, e: String) {
+        log::warn!("job {} fail: {}", self.id, e);
+        *self.state.lock().unwrap() = JobState::Failed(e, Instant::now());
+    }
+
+    fn run(&self) {
+        let input = match self.input.lock().unwrap().take() {
+            Some(input) => input,
+            None => return,
+        };
+        *self.state.lock().unwrap() = JobState::Running;
+        let time = Instant::now();
+        let progress = |finished, total| {
+            self.finished.store(finished, Ordering::SeqCst);
+            self.total.store(total, Ordering::SeqCst);
+        };
+        let r = batch::render_archive(input.archive, &input.mgr, &input.options, input.output, input.threads, &progress, &input.metrics);
+        input.metrics.render_time("job", time.elapsed().as_secs_f64());
+        match r {
+            Ok(buf) => {
+                log::info!("job {} done", self.id);
+                *self.state.lock().unwrap() = JobState::Done(buf, Instant::now());
+            }
+            Err(e) => self.fail(e.to_string()),
+        }
+    }
+}
+
+
+/// random bytes from the keys std draws from the os for hash maps
+fn random_seed() -> [u8; 16] {
+    let state = RandomState::new();
+    let mut seed = [0; 16];
+    for (i, part) in seed.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(i);
+        part.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    seed
+}
+
+
+/**
+ * batch renders queued by `POST /jobs` and run by a fixed number of threads;
+ * finished jobs are kept for `retention`, at most `MAX_FINISHED_JOBS` and `MAX_FINISHED_SIZE`;
+ * no job is started once drained
+ */
+pub struct JobQueue {
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+    queue: Mutex<VecDeque<Arc<Job>>>,
+    signal: Condvar,
+    counter: AtomicU64,
+    /// secret of the process, so that job ids cannot be guessed
+    seed: [u8; 16],
+    retention: Duration,
+    max_finished: usize,
+    max_finished_size: usize,
+    closed: AtomicBool,
+}
+
+impl JobQueue {
+
+    pub fn new(retention: Duration) -> Self {
+        JobQueue {
+            jobs: Mutex::new(HashMap::new()),
+            queue: Mutex::new(VecDeque::new()),
+            signal: Condvar::new(),
+            counter: AtomicU64::new(0),
+            seed: random_seed(),
+            retention,
+            max_finished: MAX_FINISHED_JOBS,
+            max_finished_size: MAX_FINISHED_SIZE,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// `concurrency` threads running the queued jobs, until drained or the process exits;
+    /// a job that panics is failed and its thread goes on with the next one
+    pub fn spawn_workers(self: &Arc<Self>, concurrency: usize) {
+        for i in 0 .. concurrency {
+            let this = self.clone();
+            Builder::new()
+                .name(format!("job-{}", i))
+                .spawn(move || loop {
+                    let job = {
+                        let mut queue = this.queue.lock().unwrap();
+                        loop {
+                            if this.closed.load(Ordering::SeqCst) {
+                                return;
+                            }
+                            if let Some(job) = queue.pop_front() {
+                                break job;
+                            }
+                            queue = this.signal.wait(queue).unwrap();
+                        }
+                    };
+                    if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| job.run())) {
+                        job.fail(panic_message(e));
+                    }
+                })
+                .unwrap();
+        }
+    }
+
+    /// 128 bits of sha-256 over the seed and a counter
+    fn next_id(&self) -> String {
+        let n = self.counter.fetch_add(1, Ordering::SeqCst);
+        let mut hasher = Sha256::new();
+        hasher.update(self.seed);
+        hasher.update(n.to_le_bytes());
+        hasher.finalize().iter().take(16).map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// drops the finished jobs older than the retention, then the oldest ones over the limits
+    pub fn expire(&self) {
+        let retention = self.retention;
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| !job.expired(retention));
+        let mut finished: Vec<_> = jobs.values()
+            .filter_map(|job| job.finished_at().map(|(t, size)| (t, size, job.id.clone())))
+            .collect();
+        finished.sort_by_key(|(t, _, _)| std::cmp::Reverse(*t));
+        let mut total = 0;
+        for (i, (_, size, id)) in finished.into_iter().enumerate() {
+            total += size;
+            if i >= self.max_finished || total > self.max_finished_size {
+                jobs.remove(id.as_str());
+            }
+        }
+    }
+
+    /// jobs not finished
+    pub fn pending(&self) -> usize {
+        self.jobs.lock().unwrap().values().filter(|job| job.is_pending()).count()
+    }
+
+    /// stops starting queued jobs and waits up to `timeout` for the running ones
+    pub fn drain(&self, timeout: Duration) {
+        {
+            let _queue = self.queue.lock().unwrap();
+            self.closed.store(true, Ordering::SeqCst);
+            self.signal.notify_all();
+        }
+        let start = Instant::now();
+        loop {
+            let running = self.jobs.lock().unwrap().values().filter(|job| job.is_running()).count();
+            if running == 0 {
+                break;
+            }
+            if start.elapsed() >= timeout {
+                log::warn!("{} job(s) still running after {} s", running, timeout.as_secs());
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn submit(&self, input: JobInput) -> Arc<Job> {
+        let owner = input.permit.as_ref().map(|permit| String::from(permit.id()));
+        self.enqueue(Job::new(self.next_id(), owner, Some(input)))
+    }
+
+    fn enqueue(&self, job: Job) -> Arc<Job> {
+        let job = Arc::new(job);
+        self.jobs.lock().unwrap().insert(job.id.clone(), job.clone());
+        self.queue.lock().unwrap().push_back(job.clone());
+        self.signal.notify_one();
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+}
+
+
+/// same upload as `/render/batch`; responds 202 with the job status
+pub async fn submit(s: web::Data<RenderService>, req: HttpRequest, mut payload: Multipart) -> Result<HttpResponse, ActixError> {
+
+    let permit = match authorize(&s, &req, "jobs", true) {
+        Ok(permit) => permit,
+        Err(response) => return Ok(response),
+    };
+    let mgr = match colors(&s, &req) {
+        Ok(mgr) => mgr,
+        Err(response) => return Ok(response),
+    };
+    s.jobs.expire();
+    if s.jobs.pending() >= s.options.max_tasks {
+        return Ok(HttpResponse::TooManyRequests().into())
+    }
+
+    let mut render_options = match options::from_query(req.query_string()) {
+        Ok(render_options) => render_options,
+        Err(e) => return Ok(e.response()),
+    };
+    let (archive, output) = match batch::read_upload(&mut payload, &mut render_options).await {
+        Ok(upload) => upload,
+        Err(response) => return Ok(response),
+    };
+    s.metrics.payload("job", archive.len());
+    let job = s.jobs.submit(JobInput {
+        archive,
+        options: render_options,
+        output,
+        mgr,
+        threads: s.options.workers,
+        metrics: s.metrics.clone(),
+        permit,
+    });
+    log::info!("job {} queued", job.id);
+    Ok(HttpResponse::Accepted().json(job.status()))
+}
+
+/// the job if the token of the request is allowed to see it
+fn find(s: &RenderService, req: &HttpRequest, id: &str) -> Result<Arc<Job>, HttpResponse> {
+    let permit = authorize(s, req, "jobs", false)?;
+    s.jobs.expire();
+    let job = s.jobs.get(id).ok_or_else(|| HttpResponse::NotFound().finish())?;
+    if job.visible_to(permit.as_ref().map(Permit::id)) {
+        Ok(job)
+    } else {
+        Err(HttpResponse::Forbidden().finish())
+    }
+}
+
+pub async fn status(s: web::Data<RenderService>, req: HttpRequest, id: web::Path<String>) -> Result<HttpResponse, ActixError> {
+    match find(&s, &req, id.as_str()) {
+        Ok(job) => Ok(HttpResponse::Ok().json(job.status())),
+        Err(response) => Ok(response),
+    }
+}
+
+/// 409 with the status if the job is not done
+pub async fn result(s: web::Data<RenderService>, req: HttpRequest, id: web::Path<String>) -> Result<HttpResponse, ActixError> {
+    match find(&s, &req, id.as_str()) {
+        Ok(job) => {
+            match job.result() {
+                Some(buf) => Ok(batch::archive_response(buf)),
+                None => Ok(HttpResponse::Conflict().json(job.status())),
+            }
+        }
+        Err(response) => Ok(response),
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_queue() {
+        use std::sync::Arc;
+        use std::time::Duration;
+        use super::Job;
+        use super::JobQueue;
+
+        let queue = Arc::new(JobQueue::new(Duration::from_secs(60)));
+        let a = queue.next_id();
+        assert_eq!(a.len(), 32);
+        assert_ne!(a, queue.next_id());
+
+        let job = queue.enqueue(Job::new(a.clone(), None, None));
+        assert_eq!(job.status().state, "queued");
+        assert_eq!(queue.pending(), 1);
+        assert!(queue.get(a.as_str()).is_some());
+        assert!(queue.get("unknown").is_none());
+        // the owners
+        let job = Job::new(queue.next_id(), Some(String::from("site")), None);
+        assert!(job.visible_to(Some("site")));
+        assert!(!job.visible_to(Some("bot")));
+        assert!(job.visible_to(None));
+        let job = Job::new(queue.next_id(), None, None);
+        assert!(!job.visible_to(Some("site")));
+        assert!(job.visible_to(None));
+    }
+
+    #[test]
+    fn test_expire() {
+        use std::time::Duration;
+        use std::time::Instant;
+        use bytes::Bytes;
+        use super::Job;
+        use super::JobQueue;
+        use super::JobState;
+
+        let mut queue = JobQueue::new(Duration::from_secs(10));
+        queue.max_finished = 2;
+        queue.max_finished_size = 5;
+        let now = Instant::now();
+        let finish = |queue: &JobQueue, id: &str, state: JobState| {
+            let job = queue.enqueue(Job::new(String::from(id), None, None));
+            *job.state.lock().unwrap() = state;
+        };
+        finish(&queue, "old", JobState::Failed(String::from("e"), now - Duration::from_secs(11)));
+        finish(&queue, "1", JobState::Done(Bytes::from_static(b"ab"), now - Duration::from_secs(3)));
+        finish(&queue, "2", JobState::Done(Bytes::from_static(b"ab"), now - Duration::from_secs(2)));
+        finish(&queue, "3", JobState::Failed(String::from("e"), now - Duration::from_secs(1)));
+        queue.enqueue(Job::new(String::from("queued"), None, None));
+        queue.expire();
+        let mut ids: Vec<_> = queue.jobs.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["2", "3", "queued"]);
+
+        // over the size, the oldest goes first
+        queue.max_finished = 10;
+        finish(&queue, "4", JobState::Done(Bytes::from_static(b"abcd"), now));
+        queue.expire();
+        let mut ids: Vec<_> = queue.jobs.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["3", "4", "queued"]);
+    }
+
+    #[test]
+    fn test_drain() {
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Duration;
+        use std::time::Instant;
+        use bytes::Bytes;
+        use super::Job;
+        use super::JobQueue;
+        use super::JobState;
+
+        let queue = Arc::new(JobQueue::new(Duration::from_secs(60)));
+        let running = queue.enqueue(Job::new(String::from("running"), None, None));
+        *running.state.lock().unwrap() = JobState::Running;
+        queue.enqueue(Job::new(String::from("queued"), None, None));
+        let finisher = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            *running.state.lock().unwrap() = JobState::Done(Bytes::new(), Instant::now());
+        });
+        let start = Instant::now();
+        queue.drain(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        finisher.join().unwrap();
+        // the workers started after a drain take nothing
+        queue.spawn_workers(1);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.get("queued").unwrap().status().state, "queued");
+    }
+
+}
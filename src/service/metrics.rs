@@ -0,0 +1,219 @@
+use std::io;
+use std::error::Error;
+use std::fmt::Write;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use zip::result::ZipError;
+
+use crate::color::diagnostics::Diagnostics;
+
+
+const LATENCY_BUCKETS: [f64; 12] = [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 120.0];
+
+const SIZE_BUCKETS: [f64; 9] = [1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0, 67108864.0];
+
+
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, bucket) in self.bounds.iter().zip(self.buckets.iter_mut()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, label: &str) {
+        for (bound, bucket) in self.bounds.iter().zip(self.buckets.iter()) {
+            writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, label, bound, bucket).unwrap();
+        }
+        writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, label, self.count).unwrap();
+        writeln!(out, "{}_sum{{{}}} {}", name, label, self.sum).unwrap();
+        writeln!(out, "{}_count{{{}}} {}", name, label, self.count).unwrap();
+    }
+}
+
+fn observe(histograms: &Mutex<BTreeMap<&'static str, Histogram>>, key: &'static str, bounds: &'static [f64], value: f64) {
+    histograms.lock().unwrap().entry(key).or_insert_with(|| Histogram::new(bounds)).observe(value);
+}
+
+fn downcast<'a, T: Error + 'static>(e: &'a (dyn Error + 'static)) -> Option<&'a T> {
+    e.downcast_ref::<T>().or_else(|| e.downcast_ref::<Box<T>>().map(|e| e.as_ref()))
+}
+
+/// label of an error from `Tile::load`
+pub fn decode_cause(e: &(dyn Error + 'static)) -> &'static str {
+    if let Some(e) = downcast::<ZipError>(e) {
+        match e {
+            ZipError::FileNotFound => "missing_entry",
+            ZipError::Io(_) => "io",
+            _ => "bad_zip",
+        }
+    } else if let Some(e) = downcast::<io::Error>(e) {
+        match e.kind() {
+            io::ErrorKind::InvalidData => "bad_data",
+            _ => "io",
+        }
+    } else {
+        "other"
+    }
+}
+
+
+/**
+ * counters of the server in the prometheus text format, served by `/metrics`
+ */
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<u16, u64>>,
+    latency: Mutex<BTreeMap<&'static str, Histogram>>,
+    payload: Mutex<BTreeMap<&'static str, Histogram>>,
+    decode_failures: Mutex<BTreeMap<&'static str, u64>>,
+    cache: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn request(&self, status: u16) {
+        *self.requests.lock().unwrap().entry(status).or_insert(0) += 1;
+    }
+
+    /// time of a render on `endpoint`: "render", "batch", "job" or "upload"
+    pub fn render_time(&self, endpoint: &'static str, seconds: f64) {
+        observe(&self.latency, endpoint, &LATENCY_BUCKETS, seconds);
+    }
+
+    pub fn payload(&self, endpoint: &'static str, bytes: usize) {
+        observe(&self.payload, endpoint, &SIZE_BUCKETS, bytes as f64);
+    }
+
+    pub fn decode_failure(&self, cause: &'static str) {
+        *self.decode_failures.lock().unwrap().entry(cause).or_insert(0) += 1;
+    }
+
+    /// lookup of `/render` in the render cache
+    pub fn cache_lookup(&self, hit: bool) {
+        *self.cache.lock().unwrap().entry(if hit { "hit" } else { "miss" }).or_insert(0) += 1;
+    }
+
+    /// with the gauges read at the time of the scrape, and the diagnostics of each loaded resource profile
+    pub fn render(&self, in_flight: usize, jobs_pending: usize, diagnostics: &[(&str, &Diagnostics)]) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP voxelmap_http_requests_total HTTP responses by status.\n");
+        out.push_str("# TYPE voxelmap_http_requests_total counter\n");
+        for (status, n) in self.requests.lock().unwrap().iter() {
+            writeln!(out, "voxelmap_http_requests_total{{status=\"{}\"}} {}", status, n).unwrap();
+        }
+
+        out.push_str("# HELP voxelmap_render_duration_seconds Time to decode and render, by endpoint.\n");
+        out.push_str("# TYPE voxelmap_render_duration_seconds histogram\n");
+        for (endpoint, h) in self.latency.lock().unwrap().iter() {
+            h.write(&mut out, "voxelmap_render_duration_seconds", format!("endpoint=\"{}\"", endpoint).as_str());
+        }
+
+        out.push_str("# HELP voxelmap_payload_bytes Size of uploads, by endpoint.\n");
+        out.push_str("# TYPE voxelmap_payload_bytes histogram\n");
+        for (endpoint, h) in self.payload.lock().unwrap().iter() {
+            h.write(&mut out, "voxelmap_payload_bytes", format!("endpoint=\"{}\"", endpoint).as_str());
+        }
+
+        out.push_str("# HELP voxelmap_decode_failures_total Caches that could not be decoded, by cause.\n");
+        out.push_str("# TYPE voxelmap_decode_failures_total counter\n");
+        for (cause, n) in self.decode_failures.lock().unwrap().iter() {
+            writeln!(out, "voxelmap_decode_failures_total{{cause=\"{}\"}} {}", cause, n).unwrap();
+        }
+
+        out.push_str("# HELP voxelmap_render_cache_total Lookups of /render in the render cache, by result.\n");
+        out.push_str("# TYPE voxelmap_render_cache_total counter\n");
+        for (result, n) in self.cache.lock().unwrap().iter() {
+            writeln!(out, "voxelmap_render_cache_total{{result=\"{}\"}} {}", result, n).unwrap();
+        }
+
+        out.push_str("# HELP voxelmap_renders_in_flight Renders running now.\n");
+        out.push_str("# TYPE voxelmap_renders_in_flight gauge\n");
+        writeln!(out, "voxelmap_renders_in_flight {}", in_flight).unwrap();
+
+        out.push_str("# HELP voxelmap_jobs_pending Jobs queued or running.\n");
+        out.push_str("# TYPE voxelmap_jobs_pending gauge\n");
+        writeln!(out, "voxelmap_jobs_pending {}", jobs_pending).unwrap();
+
+        out.push_str("# HELP voxelmap_unknown_blocks Block names not in the color index, by resource profile.\n");
+        out.push_str("# TYPE voxelmap_unknown_blocks gauge\n");
+        for (profile, diagnostics) in diagnostics {
+            writeln!(out, "voxelmap_unknown_blocks{{profile=\"{}\"}} {}", profile, diagnostics.missing_blocks().len()).unwrap();
+        }
+        out.push_str("# HELP voxelmap_unknown_block_lookups_total Lookups of block names not in the color index, by resource profile.\n");
+        out.push_str("# TYPE voxelmap_unknown_block_lookups_total counter\n");
+        for (profile, diagnostics) in diagnostics {
+            let lookups = diagnostics.missing_blocks().iter().map(|(_, n)| n).sum::<usize>();
+            writeln!(out, "voxelmap_unknown_block_lookups_total{{profile=\"{}\"}} {}", profile, lookups).unwrap();
+        }
+        out.push_str("# HELP voxelmap_unmatched_blocks Blocks with states matching no model, by resource profile.\n");
+        out.push_str("# TYPE voxelmap_unmatched_blocks gauge\n");
+        for (profile, diagnostics) in diagnostics {
+            writeln!(out, "voxelmap_unmatched_blocks{{profile=\"{}\"}} {}", profile, diagnostics.unmatched_states().len()).unwrap();
+        }
+
+        out
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_metrics_render() {
+        use super::Metrics;
+        use super::decode_cause;
+        use crate::color::diagnostics::Diagnostics;
+        use zip::result::ZipError;
+
+        let metrics = Metrics::new();
+        metrics.request(200);
+        metrics.request(200);
+        metrics.request(429);
+        metrics.render_time("render", 0.3);
+        metrics.decode_failure("bad_zip");
+        metrics.cache_lookup(true);
+        let diag = Diagnostics::default();
+        diag.record_missing("mod:stone");
+        diag.record_missing("mod:stone");
+        let text = metrics.render(1, 0, &[("default", &diag)]);
+        assert!(text.contains("voxelmap_http_requests_total{status=\"200\"} 2\n"));
+        assert!(text.contains("voxelmap_render_duration_seconds_bucket{endpoint=\"render\",le=\"0.25\"} 0\n"));
+        assert!(text.contains("voxelmap_render_duration_seconds_bucket{endpoint=\"render\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("voxelmap_render_duration_seconds_count{endpoint=\"render\"} 1\n"));
+        assert!(text.contains("voxelmap_render_cache_total{result=\"hit\"} 1\n"));
+        assert!(text.contains("voxelmap_unknown_block_lookups_total{profile=\"default\"} 2\n"));
+
+        let e: Box<dyn std::error::Error> = Box::new(Box::new(ZipError::FileNotFound));
+        assert_eq!(decode_cause(e.as_ref()), "missing_entry");
+        let e: Box<dyn std::error::Error> = Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "data"));
+        assert_eq!(decode_cause(e.as_ref()), "bad_data");
+    }
+
+}
@@ -0,0 +1,785 @@
+use std::path::PathBuf;
+use std::io;
+use std::io::BufReader;
+use std::fs::File;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+use bytes::BytesMut;
+use bytes::BufMut;
+use actix_rt::System;
+use actix_web::middleware;
+use actix_web::web;
+use actix_web::HttpServer;
+use actix_web::App;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Error as ActixError;
+use actix_web::http::header;
+use actix_web::dev::Service;
+use actix_files::Files;
+use actix_multipart::Multipart;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use serde::Deserialize;
+use serde::Serialize;
+use rustls::ServerConfig;
+use rustls::NoClientAuth;
+use rustls::internal::pemfile;
+
+use super::color::BakedColorManager;
+use super::application;
+use jobs::JobQueue;
+use world::WorldHost;
+use auth::Auth;
+use auth::Permit;
+use metrics::Metrics;
+use resources::LiveResources;
+use resources::Profiles;
+use resources::Resources;
+use cache::RenderCache;
+
+mod batch;
+mod jobs;
+mod world;
+mod auth;
+mod metrics;
+mod resources;
+mod options;
+mod cache;
+
+
+const MAX_TILE_SIZE: usize = 256 * 1024;
+
+#[derive(Clone)]
+pub struct RenderServerOptions {
+    host: String,
+    workers: usize,
+    max_tasks: usize,
+    compress: bool,
+    tls: Option<(PathBuf, PathBuf)>,
+    job_workers: usize,
+    job_retention: Duration,
+    worlds: Option<PathBuf>,
+    auth: Option<PathBuf>,
+    shutdown_timeout: Duration,
+    watch_resources: Option<Duration>,
+    cache_memory: usize,
+    cache_dir: Option<PathBuf>,
+    cache_disk: usize,
+}
+
+impl Default for RenderServerOptions {
+
+    fn default() -> Self {
+        RenderServerOptions {
+            host: String::from("0.0.0.0:8080"),
+            workers: num_cpus::get(),
+            max_tasks: 128,
+            compress: false,
+            tls: None,
+            job_workers: 2,
+            job_retention: Duration::from_secs(3600),
+            worlds: None,
+            auth: None,
+            shutdown_timeout: Duration::from_secs(30),
+            watch_resources: None,
+            cache_memory: 64 * 1024 * 1024,
+            cache_dir: None,
+            cache_disk: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl RenderServerOptions {
+
+    pub fn set_host(&mut self, host: &str) {
+        self.host = String::from(host);
+    }
+
+    pub fn set_workers(&mut self, num: usize) {
+        self.workers = num;
+    }
+
+    pub fn set_max_tasks(&mut self, num: usize) {
+        self.max_tasks = num;
+    }
+
+    pub fn set_compress(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    pub fn set_tls(&mut self, cert_file: PathBuf, key_file: PathBuf) {
+        self.tls = Some((cert_file, key_file));
+    }
+
+    /// number of jobs from `/jobs` running at the same time
+    pub fn set_job_workers(&mut self, num: usize) {
+        if num > 0 {
+            self.job_workers = num;
+        }
+    }
+
+    /// how long the result of a finished job is kept
+    pub fn set_job_retention(&mut self, secs: u64) {
+        self.job_retention = Duration::from_secs(secs);
+    }
+
+    /// folder of the worlds hosted by the server, one sub folder each
+    pub fn set_worlds(&mut self, dir: PathBuf) {
+        self.worlds = Some(dir);
+    }
+
+    /// token config file; if set, rendering, jobs and uploads need a bearer token
+    pub fn set_auth(&mut self, file: PathBuf) {
+        self.auth = Some(file);
+    }
+
+    /// how long in-flight renders and running jobs are waited for on SIGTERM
+    pub fn set_shutdown_timeout(&mut self, secs: u64) {
+        self.shutdown_timeout = Duration::from_secs(secs);
+    }
+
+    /// checks the resource files every `secs` seconds and reloads them when changed
+    pub fn set_watch_resources(&mut self, secs: u64) {
+        if secs > 0 {
+            self.watch_resources = Some(Duration::from_secs(secs));
+        }
+    }
+
+    /// memory for pictures rendered by `/render`, in MB; 0 keeps none in memory
+    pub fn set_cache_memory(&mut self, mb: usize) {
+        self.cache_memory = mb * 1024 * 1024;
+    }
+
+    /// folder keeping pictures rendered by `/render` across restarts
+    pub fn set_cache_dir(&mut self, dir: PathBuf) {
+        self.cache_dir = Some(dir);
+    }
+
+    /// size of the cache folder, in MB
+    pub fn set_cache_disk(&mut self, mb: usize) {
+        self.cache_disk = mb * 1024 * 1024;
+    }
+}
+
+
+pub struct RenderService {
+    options: RenderServerOptions,
+    /// the server is not ready while the default profile failed to load
+    resources: Profiles,
+    working: AtomicUsize,
+    jobs: Arc<JobQueue>,
+    worlds: Option<WorldHost>,
+    auth: Option<Auth>,
+    metrics: Arc<Metrics>,
+    /// `None` if neither memory nor a folder is given to it
+    cache: Option<Arc<RenderCache>>,
+}
+
+/// one of the `max_tasks` renders at the same time, released when dropped
+struct Slot<'a>(&'a AtomicUsize);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl RenderService {
+
+    /// a render slot, `None` if all `max_tasks` are taken
+    fn reserve(&self) -> Option<Slot<'_>> {
+        if self.working.fetch_add(1, Ordering::SeqCst) >= self.options.max_tasks {
+            self.working.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Slot(&self.working))
+    }
+
+    /// fails if the auth file cannot be read or is invalid
+    pub fn new(options: RenderServerOptions) -> io::Result<Self> {
+        let auth = match &options.auth {
+            Some(file) => {
+                let auth = Auth::load(file)
+                    .map_err(|e| io::Error::new(e.kind(), format!("auth file {}: {}", file.display(), e)))?;
+                Some(auth)
+            }
+            None => None,
+        };
+        let cache = if options.cache_memory > 0 || options.cache_dir.is_some() {
+            let disk = options.cache_dir.clone().map(|dir| (dir, options.cache_disk));
+            Some(Arc::new(RenderCache::new(options.cache_memory, disk)))
+        } else {
+            None
+        };
+        Ok(RenderService {
+            cache,
+            resources: Profiles::load(application::resource_dir()),
+            working: AtomicUsize::new(0),
+            jobs: Arc::new(JobQueue::new(options.job_retention)),
+            worlds: options.worlds.clone().map(WorldHost::new),
+            auth,
+            metrics: Arc::new(Metrics::new()),
+            options
+        })
+    }
+
+    pub fn start(self) {
+        let jobs = self.jobs.clone();
+        let timeout = self.options.shutdown_timeout;
+        jobs.spawn_workers(self.options.job_workers);
+        if let Some(interval) = self.options.watch_resources {
+            self.resources.watch(interval);
+        }
+        System::builder()
+            .name("RenderService")
+            .build()
+            .block_on(run_service(self))
+            .unwrap();
+        log::info!("server stopped, waiting for running jobs");
+        jobs.drain(timeout);
+    }
+
+}
+
+
+async fn run_service(service: RenderService) -> io::Result<()> {
+
+    const ACTIX_LOG_FORMAT: &'static str = "%a \"%r\" %s \"%{User-Agent}i\" %D";
+
+    let options = service.options.clone();
+    let service = web::Data::new(service);
+
+    let mut webfileroot = application::curdir();
+    webfileroot.push("web");
+
+    let payloadcfg = web::PayloadConfig::new(MAX_TILE_SIZE * 3 / 2);
+
+    let tls_cfg = {
+        if let Some((cert_file, key_file)) = options.tls {
+            let mut config = ServerConfig::new(NoClientAuth::new());
+            let cert_file = &mut BufReader::new(File::open(cert_file.as_path())?);
+            let key_file = &mut BufReader::new(File::open(key_file.as_path())?);
+            let cert_chain = pemfile::certs(cert_file).map_err(|_e| io::Error::from(io::ErrorKind::InvalidData))?;
+            let mut keys = pemfile::rsa_private_keys(key_file).map_err(|_e| io::Error::from(io::ErrorKind::InvalidData))?;
+            config.set_single_cert(cert_chain, keys.remove(0)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            Some(config)
+        } else {
+            None
+        }
+    };
+
+    if options.compress {
+        let server = HttpServer::new(move || {
+            let metrics = service.metrics.clone();
+            App::new()
+                .wrap(middleware::Logger::new(ACTIX_LOG_FORMAT))
+                .wrap_fn(move |req, srv| {
+                    let metrics = metrics.clone();
+                    let fut = srv.call(req);
+                    async move {
+                        let res = fut.await;
+                        match &res {
+                            Ok(res) => metrics.request(res.status().as_u16()),
+                            Err(e) => metrics.request(e.as_response_error().status_code().as_u16()),
+                        }
+                        res
+                    }
+                })
+                .wrap(middleware::Compress::default())
+                .app_data(payloadcfg.clone())
+                .app_data(service.clone())
+                .service(
+                    web::resource("/render")
+                        .route(web::post().to(render))
+                )
+                .service(
+                    web::resource("/render/options")
+                        .route(web::get().to(options::options_schema))
+                )
+                .service(
+                    web::resource("/metrics")
+                        .route(web::get().to(metrics_text))
+                )
+                .service(
+                    web::resource("/healthz")
+                        .route(web::get().to(healthz))
+                )
+                .service(
+                    web::resource("/readyz")
+                        .route(web::get().to(readyz))
+                )
+                .service(
+                    web::resource("/version")
+                        .route(web::get().to(version))
+                )
+                .service(
+                    web::resource("/profiles")
+                        .route(web::get().to(profiles))
+                )
+                .service(
+                    web::resource("/admin/reload")
+                        .route(web::post().to(reload))
+                )
+                .service(
+                    web::resource("/render/batch")
+                        .route(web::post().to(batch::render_batch))
+                )
+                .service(
+                    web::resource("/jobs")
+                        .route(web::post().to(jobs::submit))
+                )
+                .service(
+                    web::resource("/jobs/{id}")
+                        .route(web::get().to(jobs::status))
+                )
+                .service(
+                    web::resource("/jobs/{id}/result")
+                        .route(web::get().to(jobs::result))
+                )
+                .service(
+                    Files::new("/web", webfileroot.as_path()).index_file("index.html")
+                )
+                .service(
+                    web::resource("/{world}/upload")
+                        .route(web::post().to(world::upload))
+                )
+                .service(
+                    web::resource("/{world}/{file}")
+                        .route(web::get().to(world::file))
+                )
+                .service(
+                    web::resource("/{world}/{z}/{x}/{y}.png")
+                        .route(web::get().to(world::tile))
+                )
+        })
+        .workers(options.workers)
+        .shutdown_timeout(options.shutdown_timeout.as_secs());
+        if let Some(tls_cfg) = tls_cfg {
+            server
+                .bind_rustls(options.host, tls_cfg)?
+                .run()
+                .await
+        } else {
+            server
+                .bind(options.host)?
+                .run()
+                .await
+        }
+    } else {
+        let server = HttpServer::new(move || {
+            let metrics = service.metrics.clone();
+            App::new()
+                .wrap(middleware::Logger::new(ACTIX_LOG_FORMAT))
+                .wrap_fn(move |req, srv| {
+                    let metrics = metrics.clone();
+                    let fut = srv.call(req);
+                    async move {
+                        let res = fut.await;
+                        match &res {
+                            Ok(res) => metrics.request(res.status().as_u16()),
+                            Err(e) => metrics.request(e.as_response_error().status_code().as_u16()),
+                        }
+                        res
+                    }
+                })
+                .app_data(payloadcfg.clone())
+                .app_data(webfileroot.clone())
+                .app_data(service.clone())
+                .service(
+                    web::resource("/render")
+                        .route(web::post().to(render))
+                )
+                .service(
+                    web::resource("/render/options")
+                        .route(web::get().to(options::options_schema))
+                )
+                .service(
+                    web::resource("/metrics")
+                        .route(web::get().to(metrics_text))
+                )
+                .service(
+                    web::resource("/healthz")
+                        .route(web::get().to(healthz))
+                )
+                .service(
+                    web::resource("/readyz")
+                        .route(web::get().to(readyz))
+                )
+                .service(
+                    web::resource("/version")
+                        .route(web::get().to(version))
+                )
+                .service(
+                    web::resource("/profiles")
+                        .route(web::get().to(profiles))
+                )
+                .service(
+                    web::resource("/admin/reload")
+                        .route(web::post().to(reload))
+                )
+                .service(
+                    web::resource("/render/batch")
+                        .route(web::post().to(batch::render_batch))
+                )
+                .service(
+                    web::resource("/jobs")
+                        .route(web::post().to(jobs::submit))
+                )
+                .service(
+                    web::resource("/jobs/{id}")
+                        .route(web::get().to(jobs::status))
+                )
+                .service(
+                    web::resource("/jobs/{id}/result")
+                        .route(web::get().to(jobs::result))
+                )
+                .service(
+                    Files::new("/static", webfileroot.as_path()).index_file("index.html")
+                )
+                .service(
+                    web::resource("/{world}/upload")
+                        .route(web::post().to(world::upload))
+                )
+                .service(
+                    web::resource("/{world}/{file}")
+                        .route(web::get().to(world::file))
+                )
+                .service(
+                    web::resource("/{world}/{z}/{x}/{y}.png")
+                        .route(web::get().to(world::tile))
+                )
+        })
+        .workers(options.workers)
+        .shutdown_timeout(options.shutdown_timeout.as_secs());
+        if let Some(tls_cfg) = tls_cfg {
+            server
+                .bind_rustls(options.host, tls_cfg)?
+                .run()
+                .await
+        } else {
+            server
+                .bind(options.host)?
+                .run()
+                .await
+        }
+    }
+}
+
+
+/// the permit of the bearer token for `scope`, `None` if the server has no auth;
+/// the response to send instead if refused
+fn authorize(s: &RenderService, req: &HttpRequest, scope: &str, task: bool) -> Result<Option<Permit>, HttpResponse> {
+    match &s.auth {
+        Some(auth) => auth.check(req, scope, task).map(Some).map_err(|denied| denied.response()),
+        None => Ok(None),
+    }
+}
+
+
+async fn metrics_text(s: web::Data<RenderService>) -> Result<HttpResponse, ActixError> {
+    let loaded: Vec<_> = s.resources.iter()
+        .filter_map(|(name, resources)| Some((name.as_str(), resources.current().ok()?)))
+        .collect();
+    let diagnostics: Vec<_> = loaded.iter().map(|(name, resources)| (*name, resources.mgr.diagnostics())).collect();
+    let text = s.metrics.render(s.working.load(Ordering::SeqCst), s.jobs.pending(), diagnostics.as_slice());
+    let mut builder = HttpResponse::Ok();
+    builder.content_type("text/plain; version=0.0.4");
+    Ok(builder.body(text))
+}
+
+
+#[derive(Deserialize)]
+struct ProfileQuery {
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+/// `profile` in the query string of the request
+fn profile_query(req: &HttpRequest) -> Option<String> {
+    web::Query::<ProfileQuery>::from_query(req.query_string()).ok()?.into_inner().profile
+}
+
+/// the name and the resources in use of the profile of the query, or the response to send instead:
+/// 400 for an unknown profile, 503 if its resources failed to load
+fn profile_resources(s: &RenderService, req: &HttpRequest) -> Result<(String, Arc<Resources>), HttpResponse> {
+    let profile = profile_query(req).unwrap_or_else(|| String::from(Profiles::DEFAULT));
+    let resources = match s.resources.get(Some(profile.as_str())) {
+        Some(resources) => resources,
+        None => return Err(HttpResponse::BadRequest().body(format!("unknown profile: {}", profile))),
+    };
+    match resources.current() {
+        Ok(resources) => Ok((profile, resources)),
+        Err(_) => Err(HttpResponse::ServiceUnavailable().finish()),
+    }
+}
+
+/// the color manager in use for the profile of the query, or the response to send instead
+fn colors(s: &RenderService, req: &HttpRequest) -> Result<Arc<BakedColorManager>, HttpResponse> {
+    profile_resources(s, req).map(|(_, resources)| resources.mgr.clone())
+}
+
+
+/// body of `GET /readyz`
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// body of `GET /version`
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+    /// fingerprint of the default resource files, `null` if they failed to load
+    resources: Option<String>,
+}
+
+/// item of `GET /profiles` and `POST /admin/reload`
+#[derive(Serialize)]
+struct ProfileStatus {
+    name: String,
+    ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl ProfileStatus {
+
+    /// `error` of a reload, which kept the resources in use, instead of the one of the first load
+    fn of(name: &str, resources: &LiveResources, error: Option<String>) -> Self {
+        let current = resources.current();
+        ProfileStatus {
+            name: String::from(name),
+            ready: current.is_ok(),
+            fingerprint: current.as_ref().ok().map(|resources| resources.fingerprint.clone()),
+            error: error.or_else(|| current.err()),
+        }
+    }
+}
+
+async fn healthz() -> Result<HttpResponse, ActixError> {
+    Ok(HttpResponse::Ok().body("ok"))
+}
+
+/// 503 until the default resources are loaded
+async fn readyz(s: web::Data<RenderService>) -> Result<HttpResponse, ActixError> {
+    match s.resources.default_profile().current() {
+        Ok(_) => Ok(HttpResponse::Ok().json(Readiness { ready: true, error: None })),
+        Err(e) => Ok(HttpResponse::ServiceUnavailable().json(Readiness { ready: false, error: Some(e) })),
+    }
+}
+
+async fn version(s: web::Data<RenderService>) -> Result<HttpResponse, ActixError> {
+    Ok(HttpResponse::Ok().json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        resources: s.resources.default_profile().current().ok().map(|resources| resources.fingerprint.clone()),
+    }))
+}
+
+async fn profiles(s: web::Data<RenderService>) -> Result<HttpResponse, ActixError> {
+    let list: Vec<_> = s.resources.iter().map(|(name, resources)| ProfileStatus::of(name, resources, None)).collect();
+    Ok(HttpResponse::Ok().json(list))
+}
+
+/// rebuilds the color managers of all profiles, or the one of `profile` in the query;
+/// a profile failing keeps the one in use. 403 without `--auth`, so that anyone cannot keep the server busy with it
+async fn reload(s: web::Data<RenderService>, req: HttpRequest) -> Result<HttpResponse, ActixError> {
+    if s.auth.is_none() {
+        return Ok(HttpResponse::Forbidden().body("/admin/reload needs --auth"));
+    }
+    if let Err(response) = authorize(&s, &req, "admin", false) {
+        return Ok(response);
+    }
+    let targets: Vec<(String, Arc<LiveResources>)> = match profile_query(&req) {
+        Some(profile) => {
+            match s.resources.get(Some(profile.as_str())) {
+                Some(resources) => vec![(profile, resources.clone())],
+                None => return Ok(HttpResponse::BadRequest().body(format!("unknown profile: {}", profile))),
+            }
+        }
+        None => s.resources.iter().map(|(name, resources)| (name.clone(), resources.clone())).collect(),
+    };
+    let r = web::block(move || -> Result<Vec<ProfileStatus>, String> {
+        Ok(
+            targets.iter()
+                .map(|(name, resources)| ProfileStatus::of(name, resources, resources.reload().err()))
+                .collect()
+        )
+    })
+    .await;
+    match r {
+        Ok(list) => {
+            if list.iter().any(|status| status.error.is_some()) {
+                Ok(HttpResponse::InternalServerError().json(list))
+            } else {
+                Ok(HttpResponse::Ok().json(list))
+            }
+        }
+        Err(e) => {
+            Ok(
+                HttpResponse::InternalServerError()
+                    .body(e.to_string())
+                    .into()
+            )
+        }
+    }
+}
+
+
+/// `x,z.zip` to `(x, z)`
+fn parse_tile_id(filename: &str) -> Option<(i32, i32)> {
+    const EXT: &str = ".zip";
+
+    if filename.ends_with(EXT) {
+        let filename = &filename[0 .. filename.len() - EXT.len()];
+        let mut sp = filename.splitn(2, ',');
+        let x: i32 = sp.next()?.parse().ok()?;
+        let z: i32 = sp.next()?.parse().ok()?;
+        Some((x, z))
+    } else {
+        None
+    }
+}
+
+
+async fn render(s: web::Data<RenderService>, req: HttpRequest, mut payload: Multipart) -> Result<HttpResponse, ActixError> {
+
+    let _permit = match authorize(&s, &req, "render", true) {
+        Ok(permit) => permit,
+        Err(response) => return Ok(response),
+    };
+    let (profile, resources) = match profile_resources(&s, &req) {
+        Ok(resources) => resources,
+        Err(response) => return Ok(response),
+    };
+
+    if s.working.load(Ordering::SeqCst) >= s.options.max_tasks {
+        return Ok(HttpResponse::TooManyRequests().into())
+    }
+
+    let mut render_options = match options::from_query(req.query_string()) {
+        Ok(render_options) => render_options,
+        Err(e) => return Ok(e.response()),
+    };
+    let mut cache = None;
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_type = field.content_disposition().ok_or_else(|| actix_web::error::ParseError::Incomplete)?;
+        if content_type.get_name() == Some(options::FIELD) {
+            if let Err(response) = options::read_field(&mut render_options, &mut field).await {
+                return Ok(response);
+            }
+            continue;
+        }
+        if cache.is_some() {
+            continue;
+        }
+
+        let some_tile_id = content_type.get_filename().and_then(parse_tile_id);
+        let mut buf = BytesMut::with_capacity(MAX_TILE_SIZE);
+        while let Some(chunk) = field.next().await {
+            let data = chunk?;
+            if data.len() + buf.len() > MAX_TILE_SIZE {
+                return Ok(
+                    HttpResponse::PayloadTooLarge()
+                        .into()
+                );
+            }
+            buf.put(data);
+        }
+        cache = Some((some_tile_id, buf));
+    }
+    let (some_tile_id, buf) = match cache {
+        Some(cache) => cache,
+        None => return Ok(HttpResponse::NotFound().into()),
+    };
+    let tile_id = some_tile_id.unwrap_or((0, 0));
+
+    s.working.fetch_add(1, Ordering::SeqCst);
+    s.metrics.payload("render", buf.len());
+    let cache_key = s.cache.as_ref().map(|_| cache::key(&buf, tile_id, &render_options, profile.as_str(), resources.fingerprint.as_str()));
+    let cache = s.cache.clone();
+    let mgr = resources.mgr.clone();
+    let metrics = s.metrics.clone();
+    let time = Instant::now();
+    let r = web::block(move || -> Result<(Bytes, bool), String> {
+        if let (Some(cache), Some(key)) = (&cache, &cache_key) {
+            if let Some(png) = cache.get(key) {
+                return Ok((png, true));
+            }
+        }
+        let pic = batch::render_picture(buf.to_vec(), tile_id, &mgr, &render_options, &metrics)?;
+        let mut ofile = Vec::with_capacity((pic.width() * pic.height() * 4 / 3) as usize);
+        image::DynamicImage::ImageRgba8(pic).write_to(&mut ofile, image::ImageFormat::Png).map_err(|e| e.to_string())?;
+        let png = Bytes::from(ofile);
+        if let (Some(cache), Some(key)) = (&cache, &cache_key) {
+            cache.put(key, png.clone());
+        }
+        Ok((png, false))
+    })
+    .await;
+
+    s.working.fetch_sub(1, Ordering::SeqCst);
+
+    match r {
+        Ok((buf, hit)) => {
+            if s.cache.is_some() {
+                s.metrics.cache_lookup(hit);
+            }
+            if !hit {
+                s.metrics.render_time("render", time.elapsed().as_secs_f64());
+            }
+            let mut builder = HttpResponse::Ok();
+            builder.set(header::ContentType::png());
+            if s.cache.is_some() {
+                builder.header("X-Cache", if hit { "HIT" } else { "MISS" });
+            }
+            if let Some((x, z)) = some_tile_id {
+                builder.set(header::ContentDisposition {
+                    disposition: header::DispositionType::Attachment,
+                    parameters: vec![
+                        header::DispositionParam::Filename(format!("{},{}.png", x, z))
+                    ]
+                });
+            }
+            let builder = builder.body(buf);
+            Ok(
+                builder.into()
+            )
+        }
+        Err(e) => {
+            s.metrics.render_time("render", time.elapsed().as_secs_f64());
+            Ok(
+                HttpResponse::BadRequest()
+                    .body(e.to_string())
+                    .into()
+            )
+        }
+    }
+}
+
+
+// mod test {
+
+//     use super::*;
+
+//     #[test]
+//     fn test_server() {
+//         std::env::set_var("RUST_LOG", "actix_server=info,actix_web=info");
+//         env_logger::init();
+//         let options = RenderServerOptions::default();
+//         RenderService::new(options).unwrap().start();
+//     }
+
+
+// }
+
+
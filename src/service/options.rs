@@ -0,0 +1,235 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Error as ActixError;
+use actix_multipart::Field;
+use bytes::BytesMut;
+use bytes::BufMut;
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::render::RenderOptions;
+use crate::render::RenderMode;
+use crate::render::Shading;
+
+
+/// name of the multipart field with the options as a json object
+pub const FIELD: &str = "options";
+
+const MAX_FIELD_SIZE: usize = 16 * 1024;
+
+/// query keys which are not render options
+const OTHER_KEYS: [&str; 1] = ["profile"];
+
+enum Kind {
+    /// a number greater than 0
+    Positive,
+    Integer(u8, u8),
+    Boolean,
+    Choice(&'static [&'static str]),
+}
+
+impl Kind {
+
+    /// what is expected if `value` does not fit
+    fn check(&self, value: &str) -> Result<(), String> {
+        let fits = match self {
+            Kind::Positive => value.parse::<f32>().map(|v| v > 0.0 && v.is_finite()).unwrap_or(false),
+            Kind::Integer(min, max) => value.parse::<u8>().map(|v| (*min ..= *max).contains(&v)).unwrap_or(false),
+            Kind::Boolean => value == "true" || value == "false",
+            Kind::Choice(values) => values.contains(&value),
+        };
+        if fits {
+            return Ok(());
+        }
+        Err(match self {
+            Kind::Positive => String::from("a number greater than 0"),
+            Kind::Integer(min, max) => format!("an integer from {} to {}", min, max),
+            Kind::Boolean => String::from("true or false"),
+            Kind::Choice(values) => format!("one of {}", values.join(", ")),
+        })
+    }
+
+    fn schema(&self) -> Value {
+        match self {
+            Kind::Positive => json!({ "type": "number", "exclusiveMinimum": 0 }),
+            Kind::Integer(min, max) => json!({ "type": "integer", "minimum": min, "maximum": max }),
+            Kind::Boolean => json!({ "type": "boolean" }),
+            Kind::Choice(values) => json!({ "type": "string", "enum": values }),
+        }
+    }
+}
+
+struct OptionSpec {
+    name: &'static str,
+    kind: Kind,
+    description: &'static str,
+}
+
+/// every field of `RenderOptions` by its serialized name; `test_specs` checks that none is missing
+const SPECS: [OptionSpec; 7] = [
+    OptionSpec { name: "gamma", kind: Kind::Positive, description: "gamma for gamma correction" },
+    OptionSpec { name: "light", kind: Kind::Integer(0, 15), description: "environment light, blocks are at least this bright" },
+    OptionSpec { name: "mode", kind: Kind::Choice(&["color", "height"]), description: "what the picture shows: block colors, or the surface height in gray" },
+    OptionSpec { name: "shading", kind: Kind::Choice(&["none", "slope"]), description: "relief over the colors: brighter where the surface rises to the south, darker where it falls" },
+    OptionSpec { name: "seafloor", kind: Kind::Boolean, description: "draw the blocks under water" },
+    OptionSpec { name: "transparent", kind: Kind::Boolean, description: "draw glass, ice and other transparent blocks" },
+    OptionSpec { name: "foliage", kind: Kind::Boolean, description: "draw leaves, grass and other plants" },
+];
+
+
+/// body of a 400 for invalid render options
+#[derive(Serialize, Debug, PartialEq)]
+pub struct OptionError {
+    /// "unknown_option", "invalid_value", "invalid_query" or "invalid_json"
+    error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    option: Option<String>,
+    message: String,
+}
+
+impl OptionError {
+
+    fn new(error: &'static str, option: Option<&str>, message: String) -> Self {
+        OptionError {
+            error,
+            option: option.map(String::from),
+            message,
+        }
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().json(self)
+    }
+}
+
+
+fn apply(options: &mut RenderOptions, name: &str, value: &str) -> Result<(), OptionError> {
+    let spec = SPECS.iter()
+        .find(|spec| spec.name == name)
+        .ok_or_else(|| OptionError::new("unknown_option", Some(name), format!("options are {}", SPECS.iter().map(|spec| spec.name).collect::<Vec<_>>().join(", "))))?;
+    spec.kind.check(value).map_err(|expected| OptionError::new("invalid_value", Some(name), format!("expected {}, got {:?}", expected, value)))?;
+    match name {
+        "gamma" => options.set_gamma(value.parse().unwrap()),
+        "light" => options.set_env_light(value.parse().unwrap()),
+        "mode" => options.set_mode(value.parse::<RenderMode>().unwrap()),
+        "shading" => options.set_shading(value.parse::<Shading>().unwrap()),
+        "seafloor" => options.set_seafloor(value == "true"),
+        "transparent" => options.set_transparent(value == "true"),
+        "foliage" => options.set_foliage(value == "true"),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// the options in the query string, the defaults for the others
+pub fn from_query(query: &str) -> Result<RenderOptions, OptionError> {
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(query)
+        .map_err(|e| OptionError::new("invalid_query", None, e.to_string()))?
+        .into_inner();
+    let mut options = RenderOptions::default();
+    for (name, value) in pairs.iter() {
+        if !OTHER_KEYS.contains(&name.as_str()) {
+            apply(&mut options, name.as_str(), value.as_str())?;
+        }
+    }
+    Ok(options)
+}
+
+/// a json object of options over `options`, numbers and booleans may also be strings
+pub fn apply_json(options: &mut RenderOptions, data: &[u8]) -> Result<(), OptionError> {
+    let map: Map<String, Value> = serde_json::from_slice(data)
+        .map_err(|e| OptionError::new("invalid_json", None, e.to_string()))?;
+    for (name, value) in map.iter() {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            _ => return Err(OptionError::new("invalid_value", Some(name.as_str()), String::from("expected a string, a number or a boolean"))),
+        };
+        apply(options, name.as_str(), value.as_str())?;
+    }
+    Ok(())
+}
+
+/// reads the multipart field `options` over `options`; the response to send instead if invalid
+pub async fn read_field(options: &mut RenderOptions, field: &mut Field) -> Result<(), HttpResponse> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = field.next().await {
+        let data = chunk.map_err(|e| HttpResponse::from_error(e.into()))?;
+        if data.len() + buf.len() > MAX_FIELD_SIZE {
+            return Err(HttpResponse::PayloadTooLarge().finish());
+        }
+        buf.put(data);
+    }
+    apply_json(options, &buf).map_err(|e| e.response())
+}
+
+/// json schema of the options, with their defaults
+pub fn schema() -> Value {
+    let defaults = serde_json::to_value(RenderOptions::default()).unwrap();
+    let mut properties = Map::new();
+    for spec in SPECS.iter() {
+        let mut property = spec.kind.schema();
+        property["description"] = json!(spec.description);
+        property["default"] = defaults[spec.name].clone();
+        properties.insert(String::from(spec.name), property);
+    }
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "RenderOptions",
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+pub async fn options_schema() -> Result<HttpResponse, ActixError> {
+    Ok(HttpResponse::Ok().json(schema()))
+}
+
+
+mod test {
+
+    #[test]
+    fn test_specs() {
+        use super::SPECS;
+        use super::schema;
+        use crate::render::RenderOptions;
+
+        let defaults = serde_json::to_value(RenderOptions::default()).unwrap();
+        let fields: Vec<_> = defaults.as_object().unwrap().keys().cloned().collect();
+        let mut names: Vec<_> = SPECS.iter().map(|spec| String::from(spec.name)).collect();
+        names.sort();
+        assert_eq!(fields, names);
+        for spec in SPECS.iter() {
+            let default = match &defaults[spec.name] {
+                serde_json::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            assert!(spec.kind.check(default.as_str()).is_ok(), "{}", spec.name);
+        }
+        assert_eq!(schema()["properties"]["light"]["maximum"], 15);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        use super::from_query;
+        use super::apply_json;
+
+        assert!(from_query("gamma=2.2&light=7&mode=height&seafloor=false&profile=1.16").is_ok());
+        let e = from_query("light=16").unwrap_err();
+        assert_eq!((e.error, e.option.as_deref()), ("invalid_value", Some("light")));
+        let e = from_query("colour=red").unwrap_err();
+        assert_eq!((e.error, e.option.as_deref()), ("unknown_option", Some("colour")));
+
+        let mut options = from_query("").unwrap();
+        assert!(apply_json(&mut options, br#"{"gamma": 2, "shading": "slope", "foliage": false}"#).is_ok());
+        assert_eq!(apply_json(&mut options, br#"{"gamma": 0}"#).unwrap_err().error, "invalid_value");
+        assert_eq!(apply_json(&mut options, br#"{"mode": ["color"]}"#).unwrap_err().error, "invalid_value");
+        assert_eq!(apply_json(&mut options, b"[1]").unwrap_err().error, "invalid_json");
+    }
+
+}
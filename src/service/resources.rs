@@ -0,0 +1,200 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::any::Any;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::thread;
+use std::thread::Builder;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::application;
+use crate::color::BakedColorManager;
+use crate::application::RESOURCE_FILES;
+
+
+/// xxh3 of the names and contents of the resource files present, as returned by `read_resource_files`
+pub fn fingerprint(files: &[Option<Vec<u8>>]) -> String {
+    let mut hasher = Xxh3::new();
+    for (name, data) in RESOURCE_FILES.iter().zip(files) {
+        if let Some(data) = data {
+            hasher.update(name.as_bytes());
+            hasher.update(&(data.len() as u64).to_le_bytes());
+            hasher.update(data.as_slice());
+        }
+    }
+    format!("{:016x}", hasher.digest())
+}
+
+/// modified time and size of the resource files present in `dir`, to notice changes
+fn stamp(dir: &Path) -> Vec<(&'static str, Option<SystemTime>, u64)> {
+    RESOURCE_FILES.iter()
+        .filter_map(|name| {
+            let meta = fs::metadata(dir.join(name)).ok()?;
+            Some((*name, meta.modified().ok(), meta.len()))
+        })
+        .collect()
+}
+
+pub fn panic_message(e: Box<dyn Any + Send>) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        String::from(*s)
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("unknown error")
+    }
+}
+
+
+/**
+ * the colors of a resource folder, with the fingerprint of its files
+ */
+pub struct Resources {
+    pub mgr: Arc<BakedColorManager>,
+    pub fingerprint: String,
+}
+
+impl Resources {
+
+    /// the error of a missing or invalid file; the fingerprint is of the contents the colors are built from
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let files = application::read_resource_files(dir)?;
+        let fingerprint = fingerprint(files.as_slice());
+        let mgr = application::build_colormanager_with(files.as_slice())?;
+        Ok(Resources {
+            mgr: Arc::new(mgr),
+            fingerprint,
+        })
+    }
+}
+
+
+/**
+ * the resources of a folder in use by the server, swapped as a whole by `reload`;
+ * renders keep the color manager they started with
+ */
+pub struct LiveResources {
+    dir: PathBuf,
+    current: RwLock<Result<Arc<Resources>, String>>,
+    reload: Mutex<()>,
+}
+
+impl LiveResources {
+
+    pub fn load(dir: PathBuf) -> Self {
+        let current = Resources::load(dir.as_path()).map(Arc::new);
+        if let Err(e) = &current {
+            log::warn!("fail to load resources from {}: {}", dir.display(), e);
+        }
+        LiveResources {
+            dir,
+            current: RwLock::new(current),
+            reload: Mutex::new(()),
+        }
+    }
+
+    /// the error of the first load if it failed and no reload succeeded since
+    pub fn current(&self) -> Result<Arc<Resources>, String> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// rebuilds the resources from the folder; if that fails, the ones in use are kept
+    pub fn reload(&self) -> Result<Arc<Resources>, String> {
+        let _guard = self.reload.lock().unwrap();
+        match Resources::load(self.dir.as_path()) {
+            Ok(resources) => {
+                let resources = Arc::new(resources);
+                *self.current.write().unwrap() = Ok(resources.clone());
+                log::info!("resources reloaded: {}", resources.fingerprint);
+                Ok(resources)
+            }
+            Err(e) => {
+                log::warn!("fail to reload resources from {}: {}", self.dir.display(), e);
+                let mut current = self.current.write().unwrap();
+                if current.is_err() {
+                    *current = Err(e.clone());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// a thread checking the files every `interval` and reloading when they change, until the process exits
+    pub fn watch(self: &Arc<Self>, interval: Duration) {
+        let this = self.clone();
+        Builder::new()
+            .name(String::from("resource-watch"))
+            .spawn(move || {
+                let mut last = stamp(this.dir.as_path());
+                loop {
+                    thread::sleep(interval);
+                    let now = stamp(this.dir.as_path());
+                    if now != last {
+                        last = now;
+                        let _ = this.reload();
+                    }
+                }
+            })
+            .unwrap();
+    }
+}
+
+
+/**
+ * the resource profiles of the server: `default` in `resource/`,
+ * and one for each folder `resource/<profile>/` with an `index.json`, found at start
+ */
+pub struct Profiles {
+    profiles: BTreeMap<String, Arc<LiveResources>>,
+}
+
+impl Profiles {
+
+    pub const DEFAULT: &'static str = "default";
+
+    pub fn load(root: PathBuf) -> Self {
+        let mut profiles = BTreeMap::new();
+        if let Ok(entries) = fs::read_dir(root.as_path()) {
+            for entry in entries.filter_map(Result::ok) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name == Self::DEFAULT || !application::valid_profile(name.as_str()) {
+                    continue;
+                }
+                let dir = entry.path();
+                if dir.join("index.json").is_file() {
+                    profiles.insert(name, Arc::new(LiveResources::load(dir)));
+                }
+            }
+        }
+        profiles.insert(String::from(Self::DEFAULT), Arc::new(LiveResources::load(root)));
+        log::info!("resource profiles: {}", profiles.keys().cloned().collect::<Vec<_>>().join(", "));
+        Profiles {
+            profiles,
+        }
+    }
+
+    /// the default profile if `name` is `None`
+    pub fn get(&self, name: Option<&str>) -> Option<&Arc<LiveResources>> {
+        self.profiles.get(name.unwrap_or(Self::DEFAULT))
+    }
+
+    pub fn default_profile(&self) -> &Arc<LiveResources> {
+        &self.profiles[Self::DEFAULT]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Arc<LiveResources>)> {
+        self.profiles.iter()
+    }
+
+    pub fn watch(&self, interval: Duration) {
+        for resources in self.profiles.values() {
+            resources.watch(interval);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::io;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use bytes::Bytes;
+use bytes::BytesMut;
+use bytes::BufMut;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Error as ActixError;
+use actix_web::http::header;
+use actix_multipart::Multipart;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use serde::Serialize;
+use image::RgbaImage;
+use image::imageops::FilterType;
+use xxhash_rust::xxh3::xxh3_64;
+
+use super::RenderService;
+use super::authorize;
+use super::colors;
+use super::MAX_TILE_SIZE;
+use super::parse_tile_id;
+use super::batch;
+use super::batch::TileError;
+use super::batch::MAX_BATCH_SIZE;
+use super::options;
+use crate::render::data::TILESIZE;
+use crate::encoder::TileEncoder;
+use crate::tilegen;
+use crate::tilegen::Bound;
+use crate::tilegen::TileWriter;
+use crate::tilegen::viewer;
+use crate::tilegen::pathgen::Xyz;
+use crate::tilegen::pathgen::PathGenerator;
+use crate::tilegen::tile::TileId;
+use crate::tilegen::tile::LoadableImage;
+use crate::tilegen::index::HashIndex;
+use crate::tilegen::store::DirStore;
+use crate::tilegen::store::TileStore;
+
+
+/// fixed so that urls do not change when a world grows: zoom 18 is the original scale, `2^17` tiles from the center each way
+const WORLD_MAX_SCALE: i32 = 17;
+
+const CACHE_CONTROL: &str = "public, max-age=60";
+
+/// most caches in one upload; all of them, at most `MAX_BATCH_SIZE` in total, are kept in memory until rendered
+const MAX_UPLOAD_CACHES: usize = 1024;
+
+/// first path segments used by other endpoints
+const RESERVED: [&str; 11] = ["render", "jobs", "metrics", "healthz", "readyz", "version", "profiles", "admin", "static", "web", "worlds"];
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && !RESERVED.contains(&name)
+}
+
+
+/**
+ * a persistent world in `<worlds>/<name>/`: the rendered pictures `x,z.png` in `base/`,
+ * and the tiles of path mode "xyz:18" with `tiles.json` and `index.html` in `tiles/`
+ */
+pub struct World {
+    base: DirStore,
+    tiles: DirStore,
+    update: Mutex<()>,
+}
+
+/// body of `POST /{world}/upload`
+#[derive(Serialize, Default)]
+pub struct WorldUpdate {
+    rendered: Vec<String>,
+    errors: Vec<TileError>,
+    written: usize,
+}
+
+impl World {
+
+    fn open(root: PathBuf) -> Self {
+        World {
+            base: DirStore::new(root.join("base")),
+            tiles: DirStore::new(root.join("tiles")),
+            update: Mutex::new(()),
+        }
+    }
+
+    /// saves the pictures as original tiles and rebuilds their ancestors; one update at a time
+    pub fn update(&self, pictures: Vec<((i32, i32), RgbaImage)>) -> io::Result<usize> {
+        let _guard = self.update.lock().unwrap();
+        let path_gen = Xyz::new(WORLD_MAX_SCALE, TILESIZE.0);
+        let index = HashIndex::new();
+        let writer = TileWriter {
+            path_gen: &path_gen,
+            store: &self.tiles,
+            index: &index,
+            check: true,
+            encoder: TileEncoder::Png,
+        };
+        let mut changed = Vec::with_capacity(pictures.len());
+        for ((x, z), pic) in pictures {
+            self.base.put(format!("{},{}.png", x, z).as_str(), TileEncoder::Png.encode(&pic).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?)?;
+            let tile_id = TileId::new(0, x, z);
+            writer.save(&LoadableImage::Image(pic), &tile_id);
+            changed.push(tile_id);
+        }
+        tilegen::update_ancestors(changed.as_slice(), &writer, FilterType::Nearest);
+
+        let mut bound = Bound::new();
+        for tile_id in tilegen::list_tiles(&self.base).keys() {
+            bound.extend(tile_id);
+        }
+        path_gen.write_meta(&bound, 0, "png", &self.tiles)?;
+        viewer::write_viewer(&path_gen, &self.tiles, &bound, TILESIZE.0, 0, "png")?;
+        Ok(index.counts().0)
+    }
+
+    pub fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.tiles.get(key)
+    }
+}
+
+
+/**
+ * worlds hosted by the server, all in one folder
+ */
+pub struct WorldHost {
+    root: PathBuf,
+    worlds: Mutex<HashMap<String, Arc<World>>>,
+}
+
+impl WorldHost {
+
+    pub fn new(root: PathBuf) -> Self {
+        WorldHost {
+            root,
+            worlds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `None` for an invalid name, or a world never uploaded when not `create`
+    pub fn get(&self, name: &str, create: bool) -> Option<Arc<World>> {
+        if !valid_name(name) {
+            return None;
+        }
+        let mut worlds = self.worlds.lock().unwrap();
+        if let Some(world) = worlds.get(name) {
+            return Some(world.clone());
+        }
+        let root = self.root.join(name);
+        if !create && !root.is_dir() {
+            return None;
+        }
+        let world = Arc::new(World::open(root));
+        worlds.insert(String::from(name), world.clone());
+        Some(world)
+    }
+}
+
+
+fn etag(data: &[u8]) -> String {
+    format!("\"{:016x}\"", xxh3_64(data))
+}
+
+fn not_modified(req: &HttpRequest, etag: &str) -> bool {
+    match req.headers().get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        Some(tags) => tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"),
+        None => false,
+    }
+}
+
+/// the file of the world with ETag and Cache-Control, 304 if the client has it
+async fn serve(s: web::Data<RenderService>, req: HttpRequest, world: String, key: String, content_type: &'static str) -> Result<HttpResponse, ActixError> {
+    let world = match s.worlds.as_ref().and_then(|worlds| worlds.get(world.as_str(), false)) {
+        Some(world) => world,
+        None => return Ok(HttpResponse::NotFound().into()),
+    };
+    let r = web::block(move || world.get(key.as_str())).await;
+    match r {
+        Ok(Some(data)) => {
+            let etag = etag(data.as_slice());
+            if not_modified(&req, etag.as_str()) {
+                let mut builder = HttpResponse::NotModified();
+                builder.header(header::ETAG, etag);
+                builder.header(header::CACHE_CONTROL, CACHE_CONTROL);
+                return Ok(builder.finish());
+            }
+            let mut builder = HttpResponse::Ok();
+            builder.content_type(content_type);
+            builder.header(header::ETAG, etag);
+            builder.header(header::CACHE_CONTROL, CACHE_CONTROL);
+            Ok(builder.body(Bytes::from(data)))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().into()),
+        Err(e) => {
+            Ok(
+                HttpResponse::InternalServerError()
+                    .body(e.to_string())
+                    .into()
+            )
+        }
+    }
+}
+
+pub async fn tile(s: web::Data<RenderService>, req: HttpRequest, path: web::Path<(String, u32, u64, u64)>) -> Result<HttpResponse, ActixError> {
+    let (world, z, x, y) = path.into_inner();
+    serve(s, req, world, format!("{}/{}/{}.png", z, x, y), "image/png").await
+}
+
+/// `index.html` and `tiles.json` of the world
+pub async fn file(s: web::Data<RenderService>, req: HttpRequest, path: web::Path<(String, String)>) -> Result<HttpResponse, ActixError> {
+    let (world, name) = path.into_inner();
+    let content_type = match name.as_str() {
+        viewer::VIEWER => "text/html; charset=utf-8",
+        Xyz::META => "application/json",
+        _ => return Ok(HttpResponse::NotFound().into()),
+    };
+    serve(s, req, world, name, content_type).await
+}
+
+/// multipart with caches `x,z.zip`, one in each field, and an optional json field `options`; renders them into the world and updates its tiles.
+/// 413 for more than `MAX_UPLOAD_CACHES` caches or `MAX_BATCH_SIZE` in total
+pub async fn upload(s: web::Data<RenderService>, req: HttpRequest, world: web::Path<String>, mut payload: Multipart) -> Result<HttpResponse, ActixError> {
+    let _permit = match authorize(&s, &req, "upload", true) {
+        Ok(permit) => permit,
+        Err(response) => return Ok(response),
+    };
+    let mgr = match colors(&s, &req) {
+        Ok(mgr) => mgr,
+        Err(response) => return Ok(response),
+    };
+    let world = match s.worlds.as_ref().and_then(|worlds| worlds.get(world.as_str(), true)) {
+        Some(world) => world,
+        None => return Ok(HttpResponse::NotFound().into()),
+    };
+
+    // held while the caches are buffered too, so uploads in progress count against `max_tasks`
+    let _slot = match s.reserve() {
+        Some(slot) => slot,
+        None => return Ok(HttpResponse::TooManyRequests().into()),
+    };
+
+    let mut render_options = match options::from_query(req.query_string()) {
+        Ok(render_options) => render_options,
+        Err(e) => return Ok(e.response()),
+    };
+    let mut caches = Vec::new();
+    let mut total = 0;
+    while let Some(mut field) = payload.try_next().await? {
+        if field.content_disposition().and_then(|cd| cd.get_name().map(|name| name == options::FIELD)).unwrap_or(false) {
+            if let Err(response) = options::read_field(&mut render_options, &mut field).await {
+                return Ok(response);
+            }
+            continue;
+        }
+        let filename = field.content_disposition()
+            .and_then(|cd| cd.get_filename().map(String::from))
+            .unwrap_or_default();
+        let tile_id = match parse_tile_id(filename.as_str()) {
+            Some(tile_id) => tile_id,
+            None => {
+                return Ok(
+                    HttpResponse::BadRequest()
+                        .body(format!("invalid cache name: {}", filename))
+                        .into()
+                );
+            }
+        };
+        if caches.len() >= MAX_UPLOAD_CACHES {
+            return Ok(
+                HttpResponse::PayloadTooLarge()
+                    .body(format!("more than {} caches", MAX_UPLOAD_CACHES))
+                    .into()
+            );
+        }
+        let mut buf = BytesMut::with_capacity(MAX_TILE_SIZE);
+        while let Some(chunk) = field.next().await {
+            let data = chunk?;
+            if data.len() + buf.len() > MAX_TILE_SIZE || data.len() + buf.len() + total > MAX_BATCH_SIZE {
+                return Ok(
+                    HttpResponse::PayloadTooLarge()
+                        .into()
+                );
+            }
+            buf.put(data);
+        }
+        total += buf.len();
+        caches.push((filename, tile_id, buf.to_vec()));
+    }
+
+    s.metrics.payload("upload", total);
+    let metrics = s.metrics.clone();
+    let time = Instant::now();
+    let r = web::block(move || -> Result<WorldUpdate, String> {
+        let mut update = WorldUpdate::default();
+        let mut pictures = Vec::with_capacity(caches.len());
+        for (filename, tile_id, data) in caches {
+            match batch::render_picture(data, tile_id, &mgr, &render_options, &metrics) {
+                Ok(pic) => {
+                    pictures.push((tile_id, pic));
+                    update.rendered.push(filename);
+                }
+                Err(error) => update.errors.push(TileError { file: filename, error }),
+            }
+        }
+        update.written = world.update(pictures).map_err(|e| e.to_string())?;
+        Ok(update)
+    })
+    .await;
+
+    s.metrics.render_time("upload", time.elapsed().as_secs_f64());
+
+    match r {
+        Ok(update) => Ok(HttpResponse::Ok().json(update)),
+        Err(e) => {
+            Ok(
+                HttpResponse::InternalServerError()
+                    .body(e.to_string())
+                    .into()
+            )
+        }
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_valid_name() {
+        use super::valid_name;
+        use super::RESERVED;
+
+        assert!(valid_name("survival-2"));
+        assert!(valid_name("a_b"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("a/b"));
+        assert!(!valid_name(".."));
+        assert!(!valid_name("x".repeat(65).as_str()));
+        for name in RESERVED.iter() {
+            assert!(!valid_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_not_modified() {
+        use actix_web::http::header;
+        use actix_web::test::TestRequest;
+        use super::etag;
+        use super::not_modified;
+
+        let tag = etag(b"tile");
+        assert_eq!(tag, etag(b"tile"));
+        assert_ne!(tag, etag(b"other"));
+        assert!(tag.starts_with('"') && tag.ends_with('"'));
+
+        let req = TestRequest::default().to_http_request();
+        assert!(!not_modified(&req, tag.as_str()));
+        let req = TestRequest::with_header(header::IF_NONE_MATCH, format!("\"0\", {}", tag)).to_http_request();
+        assert!(not_modified(&req, tag.as_str()));
+        let req = TestRequest::with_header(header::IF_NONE_MATCH, etag(b"other")).to_http_request();
+        assert!(!not_modified(&req, tag.as_str()));
+        let req = TestRequest::with_header(header::IF_NONE_MATCH, "*").to_http_request();
+        assert!(not_modified(&req, tag.as_str()));
+    }
+
+}
@@ -0,0 +1,126 @@
+use std::io;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use image::RgbaImage;
+use xxhash_rust::xxh3::Xxh3;
+
+use super::store::TileStore;
+use crate::encoder::TileEncoder;
+
+
+/// xxh3 of the size, the raw pixels and the encoder of a tile
+pub fn pixel_hash(img: &RgbaImage, encoder: TileEncoder) -> u64 {
+    let mut hasher = Xxh3::new();
+    hasher.update(&img.width().to_le_bytes());
+    hasher.update(&img.height().to_le_bytes());
+    hasher.update(img.as_raw());
+    hasher.update(format!("{:?}", encoder).as_bytes());
+    hasher.digest()
+}
+
+
+/**
+ * sidecar `tiles.xxh3` next to the tiles, one `<hash>  <key>` line for each tile of the last run;
+ * a tile whose hash is unchanged is skipped without reading the old one
+ */
+#[derive(Default)]
+pub struct HashIndex {
+    old: HashMap<String, u64>,
+    new: Mutex<BTreeMap<String, u64>>,
+    written: AtomicUsize,
+    skipped: AtomicUsize,
+}
+
+impl HashIndex {
+
+    pub const FILE: &'static str = "tiles.xxh3";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// index of the last run in the store, empty if there is none
+    pub fn load(store: &dyn TileStore) -> Self {
+        let mut index = Self::new();
+        match store.get(Self::FILE) {
+            Ok(Some(data)) => {
+                for line in String::from_utf8_lossy(&data).lines() {
+                    let mut sp = line.splitn(2, "  ");
+                    if let (Some(hash), Some(key)) = (sp.next(), sp.next()) {
+                        if let Ok(hash) = u64::from_str_radix(hash, 16) {
+                            index.old.insert(String::from(key), hash);
+                            continue;
+                        }
+                    }
+                    log::warn!("invalid line in {}: {}", Self::FILE, line);
+                }
+            }
+            Ok(None) => { }
+            Err(e) => log::warn!("load {} fail: {}", Self::FILE, e),
+        }
+        index
+    }
+
+    /// `Some(true)` if the hash is the same as the last run, `None` if the tile is not in the last run
+    pub fn unchanged(&self, key: &str, hash: u64) -> Option<bool> {
+        self.old.get(key).map(|old| *old == hash)
+    }
+
+    pub fn record(&self, key: &str, hash: u64, written: bool) {
+        self.new.lock().unwrap().insert(String::from(key), hash);
+        if written {
+            self.written.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// `(written, skipped)`
+    pub fn counts(&self) -> (usize, usize) {
+        (self.written.load(Ordering::Relaxed), self.skipped.load(Ordering::Relaxed))
+    }
+
+    pub fn save(&self, store: &dyn TileStore) -> io::Result<()> {
+        let mut data = String::new();
+        for (key, hash) in self.new.lock().unwrap().iter() {
+            data.push_str(format!("{:016x}  {}\n", hash, key).as_str());
+        }
+        store.put(Self::FILE, data.into_bytes())
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_hash_index() {
+        use super::HashIndex;
+        use super::pixel_hash;
+        use super::super::store::MemoryStore;
+        use crate::encoder::TileEncoder;
+        use image::Rgba;
+        use image::RgbaImage;
+
+        let img = RgbaImage::from_pixel(4, 4, Rgba::from([1, 2, 3, 255]));
+        let hash = pixel_hash(&img, TileEncoder::Png);
+        assert_ne!(hash, pixel_hash(&img, TileEncoder::WebpLossy(80.0)));
+        assert_ne!(hash, pixel_hash(&RgbaImage::from_pixel(2, 8, Rgba::from([1, 2, 3, 255])), TileEncoder::Png));
+
+        let store = MemoryStore::new();
+        let index = HashIndex::load(&store);
+        assert_eq!(index.unchanged("0/0,0.png", hash), None);
+        index.record("0/0,0.png", hash, true);
+        index.record("0/1,0.png", 7, false);
+        assert_eq!(index.counts(), (1, 1));
+        index.save(&store).unwrap();
+
+        let index = HashIndex::load(&store);
+        assert_eq!(index.unchanged("0/0,0.png", hash), Some(true));
+        assert_eq!(index.unchanged("0/1,0.png", hash), Some(false));
+    }
+
+}
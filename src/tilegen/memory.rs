@@ -0,0 +1,68 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+
+/**
+ * bytes of the decoded images held by the branches, and the most held at once
+ */
+#[derive(Default)]
+pub struct MemoryMeter {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl MemoryMeter {
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn alloc(&self, bytes: usize) {
+        let current = self.current.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+    }
+
+    pub fn free(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    #[cfg(test)]
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+
+/// upper bound of the bytes held by one branch with root at `depth` above the original tiles:
+/// the walk is depth first, so each level keeps at most 3 finished siblings
+/// while the 4th is built, plus the merge buffer and one over-zoom part
+pub fn branch_bytes(depth: i32, tile_bytes: usize) -> usize {
+    (3 * depth.max(0) as usize + 10) * tile_bytes
+}
+
+
+pub fn format_mb(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+
+mod test {
+
+    #[test]
+    fn test_memory_meter() {
+        use super::MemoryMeter;
+
+        let meter = MemoryMeter::new();
+        meter.alloc(100);
+        meter.alloc(50);
+        meter.free(100);
+        meter.alloc(20);
+        assert_eq!(meter.current(), 70);
+        assert_eq!(meter.peak(), 150);
+    }
+
+}
@@ -1,36 +1,90 @@
 pub mod pathgen;
 pub mod tile;
+pub mod stitch;
+pub mod viewer;
+pub mod store;
+pub mod index;
+pub mod memory;
 
 use std::io;
 use std::str::FromStr;
-use std::path::Path;
 use std::path::PathBuf;
 use std::collections::HashMap;
+#[cfg(any(test, feature = "service"))]
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
 use std::thread::Builder;
 
 
+use serde::Serialize;
 use image::imageops::FilterType;
 
 use tile::LoadableImage;
 use tile::TileId;
 use tile::TileQTreeIterator;
 use pathgen::PathGenerator;
+use store::DirStore;
+use store::StoreMode;
+use store::TileStore;
+use index::HashIndex;
+use memory::MemoryMeter;
+use crate::render::data::TILESIZE;
+use crate::encoder::TileEncoder;
+
+/**
+ * where and how the tiles of `merge_branch` are saved
+ */
+pub struct TileWriter<'a> {
+    pub path_gen: &'a dyn PathGenerator,
+    pub store: &'a dyn TileStore,
+    pub index: &'a HashIndex,
+    pub check: bool,
+    pub encoder: TileEncoder,
+}
+
+impl<'a> TileWriter<'a> {
+
+    pub fn key(&self, tile_id: &TileId) -> String {
+        format!("{}.{}", self.path_gen.generate(tile_id.x, tile_id.z, tile_id.scale), self.encoder.extension())
+    }
+
+    pub fn save(&self, img: &LoadableImage, tile_id: &TileId) {
+        let key = self.key(tile_id);
+        match img.save(key.as_str(), self.store, self.index, self.check, self.encoder) {
+            Err(e) => {
+                log::warn!("[{}] tile {} @{} fail: {}", thread::current().name().unwrap_or_default(), tile_id, key, e);
+            },
+            Ok(b) => {
+                if b {
+                   log::info!("[{}] tile {} generated", thread::current().name().unwrap_or_default(), tile_id);
+                }
+            }
+        }
+    }
+}
 
-pub fn merge_branch(root: TileId, cache: &mut HashMap<TileId, LoadableImage>, path_gen: &dyn PathGenerator, filter: FilterType, check: bool) {
-    for tile_id in TileQTreeIterator::new(root, 0) {
+/// original tiles are loaded from `source`;
+/// `overzoom` levels below the original scale are the original tiles enlarged 2x, 4x, ... and split;
+/// only the nodes above the tiles in `cache` are visited, and the images held are counted in `meter`
+pub fn merge_branch(root: TileId, cache: &mut HashMap<TileId, LoadableImage>, source: &dyn TileStore, writer: &TileWriter, filter: FilterType, overzoom: u32, meter: &MemoryMeter) {
+    let nodes = TileQTreeIterator::over(root.clone(), 0, cache.keys());
+    for tile_id in nodes {
         if tile_id.is_origin() {
             if let Some(img) = cache.get_mut(&tile_id) {
-                img.ensure();
-                let p = path_gen.generate(tile_id.x, tile_id.z, tile_id.scale);
-                match img.save(&p, check) {
-                    Err(e) => {
-                        log::warn!("[{}] tile {} @{} fail: {}", thread::current().name().unwrap_or_default(), tile_id, p.display(), e);
-                    },
-                    Ok(b) => {
-                        if b {
-                           log::info!("[{}] tile {} generated", thread::current().name().unwrap_or_default(), tile_id);
+                img.ensure(source);
+                meter.alloc(img.bytes());
+                writer.save(img, &tile_id);
+                for level in 1 ..= overzoom {
+                    let n = 1 << level;
+                    for j in 0 .. n {
+                        for i in 0 .. n {
+                            let child = TileId::new(-(level as i32), tile_id.x * n + i, tile_id.z * n + j);
+                            let part = img.zoom_in(level, i as u32, j as u32);
+                            meter.alloc(part.bytes());
+                            writer.save(&part, &child);
+                            meter.free(part.bytes());
                         }
                     }
                 }
@@ -41,20 +95,104 @@ pub fn merge_branch(root: TileId, cache: &mut HashMap<TileId, LoadableImage>, pa
             let tl = cache.remove(&tile_id.topleft()).unwrap_or_default();
             let tr = cache.remove(&tile_id.topright()).unwrap_or_default();
             let img = LoadableImage::merge(&tl, &tr, &bl, &br, filter);
-            let p = path_gen.generate(tile_id.x, tile_id.z, tile_id.scale);
-            match img.save(&p, check) {
-                Err(e) => {
-                    log::warn!("[{}] tile {} @{} fail: {}", thread::current().name().unwrap_or_default(), tile_id, p.display(), e);
-                },
-                Ok(b) => {
-                    if b {
-                       log::info!("[{}] tile {} generated", thread::current().name().unwrap_or_default(), tile_id);
-                    }
-                }
-            }
+            // the 2x buffer of the merge is counted along with its result
+            meter.alloc(5 * img.bytes());
+            meter.free(4 * img.bytes() + bl.bytes() + br.bytes() + tl.bytes() + tr.bytes());
+            writer.save(&img, &tile_id);
             cache.insert(tile_id, img);  
         }
     }
+    if let Some(img) = cache.remove(&root) {
+        meter.free(img.bytes());
+    }
+}
+
+
+/// rebuilds the ancestors of the `changed` original tiles from their children already in the store of `writer`,
+/// level by level up to the max scale; for updating a pyramid in place
+#[cfg(any(test, feature = "service"))]
+pub fn update_ancestors(changed: &[TileId], writer: &TileWriter, filter: FilterType) {
+    let max_scale = writer.path_gen.get_max_scale();
+    let mut dirty: HashSet<TileId> = changed.iter().filter(|tile| tile.scale < max_scale).map(TileId::parent).collect();
+    while !dirty.is_empty() {
+        let mut next = HashSet::new();
+        for tile_id in dirty.into_iter() {
+            let children: Vec<LoadableImage> = [tile_id.topleft(), tile_id.topright(), tile_id.bottomleft(), tile_id.bottomright()].iter()
+                .map(|child| {
+                    let mut img = LoadableImage::new(writer.key(child));
+                    img.ensure(writer.store);
+                    img
+                })
+                .collect();
+            let img = LoadableImage::merge(&children[0], &children[1], &children[2], &children[3], filter);
+            writer.save(&img, &tile_id);
+            if tile_id.scale < max_scale {
+                next.insert(tile_id.parent());
+            }
+        }
+        dirty = next;
+    }
+}
+
+
+/// rendered tiles `x,z.png` or `x,z.webp` at the top level of the store as scale-0 tiles
+pub fn list_tiles(store: &dyn TileStore) -> HashMap<TileId, LoadableImage> {
+    const EXTS: [&str; 2] = ["png", "webp"];
+    match store.list("") {
+        Ok(keys) => {
+            keys.into_iter().filter_map(|key| {
+                let (stem, ext) = key.rsplit_once('.')?;
+                if !EXTS.contains(&ext) {
+                    return None;
+                }
+                let mut sp = stem.splitn(2, ',');
+                let x: i32 = sp.next()?.parse().ok()?;
+                let z: i32 = sp.next()?.parse().ok()?;
+                Some((TileId::new(0, x, z), LoadableImage::new(key)))
+            }).collect()
+        }
+        Err(e) => {
+            log::warn!("list tiles fail: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+
+/// the whole pyramid of `cache` with its layout meta and viewer into `store`, on the current thread;
+/// for short-lived outputs such as the batch render of the server, returns the number of tiles written
+#[cfg(any(test, feature = "service"))]
+pub fn generate_into(cache: HashMap<TileId, LoadableImage>, source: &dyn TileStore, store: &dyn TileStore, path_mode: &PathMode, encoder: TileEncoder, filter: FilterType) -> usize {
+    let mut parts: Vec<HashMap<TileId, LoadableImage>> = vec![HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new()];
+    let mut bound = Bound::new();
+    for (tile, image) in cache.into_iter() {
+        bound.extend(&tile);
+        parts[tile.side()].insert(tile, image);
+    }
+    let path_gen = path_mode.extract(&bound);
+    let ext = encoder.extension();
+    if let Err(e) = path_gen.write_meta(&bound, 0, ext, store) {
+        log::warn!("write tile layout meta fail: {}", e);
+    }
+    if let Err(e) = viewer::write_viewer(path_gen.as_ref(), store, &bound, TILESIZE.0, 0, ext) {
+        log::warn!("write viewer fail: {}", e);
+    }
+    let index = HashIndex::new();
+    let meter = MemoryMeter::new();
+    let writer = TileWriter {
+        path_gen: path_gen.as_ref(),
+        store,
+        index: &index,
+        check: false,
+        encoder,
+    };
+    for (i, mut cache_part) in parts.into_iter().enumerate() {
+        if !cache_part.is_empty() {
+            let root = TileId::new(path_gen.get_max_scale(), (i & 0x1) as i32 - 1, ((i & 0x2) >> 1) as i32 - 1);
+            merge_branch(root, &mut cache_part, source, &writer, filter, 0, &meter);
+        }
+    }
+    index.counts().0
 }
 
 
@@ -65,6 +203,11 @@ pub struct TileGeneratorOptions {
     output_folder: PathBuf,
     path_mode: PathMode,
     check: bool,
+    viewer: bool,
+    overzoom: u32,
+    encoder: TileEncoder,
+    store_mode: StoreMode,
+    memory_hint: Option<usize>,
 }
 
 impl TileGeneratorOptions {
@@ -77,6 +220,11 @@ impl TileGeneratorOptions {
             output_folder,
             path_mode,
             check: false,
+            viewer: false,
+            overzoom: 0,
+            encoder: TileEncoder::default(),
+            store_mode: StoreMode::default(),
+            memory_hint: None,
         }
     }
 
@@ -99,6 +247,32 @@ impl TileGeneratorOptions {
         self.check = check;
     }
 
+    pub fn set_viewer(&mut self, viewer: bool) {
+        self.viewer = viewer;
+    }
+
+    /// levels enlarged beyond the original tiles, up to 3 (8x)
+    pub fn set_overzoom(&mut self, overzoom: u32) {
+        if overzoom <= 3 {
+            self.overzoom = overzoom;
+        }
+    }
+
+    pub fn set_encoder(&mut self, encoder: TileEncoder) {
+        self.encoder = encoder;
+    }
+
+    /// with `zip` or `mbtiles`, the output folder is the archive file
+    pub fn set_store_mode(&mut self, store_mode: StoreMode) {
+        self.store_mode = store_mode;
+    }
+
+    /// MB of decoded images the parallel branches should fit in, by the estimate of `memory::branch_bytes`;
+    /// only the number of workers is lowered, a branch holds what it needs
+    pub fn set_memory_hint(&mut self, mb: usize) {
+        self.memory_hint = Some(mb * 1024 * 1024);
+    }
+
     // pub fn build_path_generator(&self, mode: &str) -> Option<Arc<dyn PathGenerator + Send + Sync>> {
     //     use pathgen::Layer;
 
@@ -117,6 +291,7 @@ impl TileGeneratorOptions {
 }
 
 
+#[derive(Serialize)]
 pub struct Bound {
     pub xmin: i32,
     pub xmax: i32,
@@ -156,6 +331,14 @@ pub fn ceil_log2(x: i32) -> i32 {
     32 - (x - 1).leading_zeros() as i32
 }
 
+/// scale of the 4 roots `(-1|0, -1|0)` so that they cover the bound, `[-2^scale, 2^scale)` on each axis
+fn quad_scale(bound: &Bound) -> i32 {
+    use std::cmp::max;
+
+    let extent = max(max(-bound.xmin, bound.xmax + 1), max(-bound.zmin, bound.zmax + 1));
+    ceil_log2(max(extent, 1))
+}
+
 #[derive(Debug)]
 pub enum PathMode {
     Layer {
@@ -164,8 +347,11 @@ pub enum PathMode {
         max_zoom: Option<i32>,
     },
     Tree {
-        
-    }
+        names: [String; 4],
+    },
+    Xyz {
+        max_zoom: Option<i32>,
+    },
 }
 
 impl FromStr for PathMode {
@@ -229,7 +415,32 @@ impl FromStr for PathMode {
                 }
             },
             "tree" => {
-                unimplemented!();
+                let names = if let Some(params) = mode_sp.next() {
+                    let names: Vec<&str> = params.split(',').collect();
+                    if names.len() != 4 || names.iter().any(|n| n.is_empty()) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "names"));
+                    }
+                    [names[0], names[1], names[2], names[3]]
+                } else {
+                    ["0", "1", "2", "3"]
+                };
+                Ok(Self::Tree {
+                    names: [String::from(names[0]), String::from(names[1]), String::from(names[2]), String::from(names[3])],
+                })
+            },
+            "xyz" => {
+                let max_zoom = if let Some(z) = mode_sp.next() {
+                    let z: i32 = z.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "maxZoom"))?;
+                    if z < 1 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "maxZoom"));
+                    }
+                    Some(z)
+                } else {
+                    None
+                };
+                Ok(Self::Xyz {
+                    max_zoom,
+                })
             },
             _ => {
                 Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported"))
@@ -242,9 +453,11 @@ impl FromStr for PathMode {
 
 impl PathMode {
 
-    pub fn extract(&self, bound: &Bound, root: &Path) -> Arc<dyn PathGenerator + Send + Sync> {
+    pub fn extract(&self, bound: &Bound) -> Arc<dyn PathGenerator + Send + Sync> {
         use std::cmp::max;
         use pathgen::Layer;
+        use pathgen::Tree;
+        use pathgen::Xyz;
 
         match self {
             Self::Layer { reverse, min_zoom, max_zoom } => {
@@ -255,13 +468,24 @@ impl PathMode {
                     min_zoom + ceil_log2(max(max(bound.xmin.abs(), bound.xmax.abs()), max(bound.zmin.abs(), bound.zmax.abs())))
                 };
                 if *reverse {
-                    Arc::new(Layer::new(max_zoom, -1, min_zoom, PathBuf::from(root)))
+                    Arc::new(Layer::new(max_zoom, -1, min_zoom))
                 } else {
-                    Arc::new(Layer::new(min_zoom, 1, max_zoom, PathBuf::from(root)))
+                    Arc::new(Layer::new(min_zoom, 1, max_zoom))
                 }
             },
-            Self::Tree { } => {
-                unimplemented!()
+            Self::Tree { names } => {
+                Arc::new(Tree::new(quad_scale(bound), names.clone()))
+            },
+            Self::Xyz { max_zoom } => {
+                let mut max_scale = quad_scale(bound);
+                if let Some(z) = max_zoom {
+                    if z - 1 < max_scale {
+                        log::warn!("maxZoom {} is too small for the tiles, use {}", z, max_scale + 1);
+                    } else {
+                        max_scale = z - 1;
+                    }
+                }
+                Arc::new(Xyz::new(max_scale, TILESIZE.0))
             }
         }
     }
@@ -270,34 +494,21 @@ impl PathMode {
 
 pub struct TileGenerator {
     options: TileGeneratorOptions,
+    source: Arc<dyn TileStore>,
 }
 
 impl TileGenerator {
 
     pub fn new(options: TileGeneratorOptions) -> Self {
+        let source = Arc::new(DirStore::new(options.input_folder.clone()));
         TileGenerator {
             options,
+            source,
         }
     }
 
     pub fn list_files(&self) -> HashMap<TileId, LoadableImage> {
-        const EXT: &'static str = ".png";
-        if let Ok(read_dir) = self.options.input_folder.read_dir() {
-            read_dir.filter_map(|entry| {
-                let src = entry.ok()?.path();
-                let filename = src.file_name()?.to_str()?;
-                if !filename.ends_with(EXT) {
-                    return None;
-                }
-                let filename = &filename[0 .. filename.len() - EXT.len()];
-                let mut sp = filename.splitn(2, ',');
-                let x: i32 = sp.next()?.parse().ok()?;
-                let z: i32 = sp.next()?.parse().ok()?;
-                Some((TileId::new(0, x, z), LoadableImage::new(src)))
-            }).collect()
-        } else {
-            HashMap::new()
-        }
+        list_tiles(self.source.as_ref())
     }
 
     pub fn generate_tile(&self, cache: HashMap<TileId, LoadableImage>) {
@@ -312,29 +523,94 @@ impl TileGenerator {
             bound.extend(&tile);
             parts[tile.side()].2.insert(tile, image);
         }
-        let path_gen = self.options.path_mode.extract(&bound, self.options.output_folder.as_path());
+        let output = self.options.output_folder.as_path();
+        let path_gen = match (self.options.store_mode, &self.options.path_mode) {
+            (StoreMode::MBTiles, PathMode::Xyz { .. }) | (StoreMode::Dir, _) | (StoreMode::Zip, _) => self.options.path_mode.extract(&bound),
+            (StoreMode::MBTiles, _) => {
+                log::warn!("mbtiles is stored with path mode \"xyz\"");
+                PathMode::Xyz { max_zoom: None }.extract(&bound)
+            }
+        };
+        let min_scale = -(self.options.overzoom as i32);
+        let ext = self.options.encoder.extension();
+        let max_zoom = path_gen.get_max_scale() + 1 - min_scale;
+        let store = match self.options.store_mode.open(output, 1, max_zoom, ext, self.options.check) {
+            Ok(store) => store,
+            Err(e) => {
+                log::warn!("open output {} fail: {}", output.display(), e);
+                return;
+            }
+        };
+        let index = Arc::new(HashIndex::load(store.as_ref()));
+        if let Err(e) = path_gen.write_meta(&bound, min_scale, ext, store.as_ref()) {
+            log::warn!("write tile layout meta fail: {}", e);
+        }
+        if self.options.viewer {
+            if let Err(e) = viewer::write_viewer(path_gen.as_ref(), store.as_ref(), &bound, TILESIZE.0, self.options.overzoom, ext) {
+                log::warn!("write viewer fail: {}", e);
+            }
+        }
+        let parts: Vec<_> = parts.into_iter().filter(|(_, _, cache_part)| !cache_part.is_empty()).collect();
+        let branch_bytes = memory::branch_bytes(path_gen.get_max_scale(), TILESIZE.0 as usize * TILESIZE.1 as usize * 4);
+        let mut workers = if self.options.multi_thread_mode { parts.len() } else { 1 };
+        if let Some(memory_hint) = self.options.memory_hint {
+            if branch_bytes > memory_hint {
+                log::warn!("memory hint {} is below the {} one branch may hold", memory::format_mb(memory_hint), memory::format_mb(branch_bytes));
+            }
+            workers = workers.min(memory_hint / branch_bytes).max(1);
+        }
+        let parts = Arc::new(Mutex::new(parts));
+        let meter = Arc::new(MemoryMeter::new());
         let mut ths = Vec::new();
-        for (x, z, mut cache_part) in parts.into_iter() {
-            if cache_part.len() > 0 {
-                let path_gen = path_gen.clone();
-                let root = TileId::new(path_gen.get_max_scale(), x, z);
-                let filter = self.options.filter.clone();
-                let check = self.options.check;
-                let tasks = move || {
-                    let path_gen = path_gen.as_ref();
-                    merge_branch(root, &mut cache_part, path_gen, filter, check)
+        for worker in 0 .. workers {
+            let parts = parts.clone();
+            let path_gen = path_gen.clone();
+            let source = self.source.clone();
+            let store = store.clone();
+            let index = index.clone();
+            let meter = meter.clone();
+            let filter = self.options.filter;
+            let check = self.options.check;
+            let overzoom = self.options.overzoom;
+            let encoder = self.options.encoder;
+            let tasks = move || {
+                let writer = TileWriter {
+                    path_gen: path_gen.as_ref(),
+                    store: store.as_ref(),
+                    index: index.as_ref(),
+                    check,
+                    encoder,
                 };
-                if self.options.multi_thread_mode {
-                    let th = Builder::new().name(format!("work-({},{})", x, z)).spawn(tasks).unwrap();
-                    ths.push(th);
-                } else {
-                    tasks();
+                loop {
+                    let part = parts.lock().unwrap().pop();
+                    let (x, z, mut cache_part) = match part {
+                        Some(part) => part,
+                        None => break,
+                    };
+                    let root = TileId::new(path_gen.get_max_scale(), x, z);
+                    log::info!("[{}] branch {} of {} tiles", thread::current().name().unwrap_or_default(), root, cache_part.len());
+                    merge_branch(root, &mut cache_part, source.as_ref(), &writer, filter, overzoom, meter.as_ref());
                 }
+            };
+            if workers > 1 {
+                let th = Builder::new().name(format!("work-{}", worker)).spawn(tasks).unwrap();
+                ths.push(th);
+            } else {
+                tasks();
             }
         }
         for th in ths {
             th.join().unwrap();
         }
+        log::info!("> peak image memory: {} with {} worker(s)", memory::format_mb(meter.peak()), workers);
+        let (written, skipped) = index.counts();
+        log::info!("> tiles: {} written, {} skipped", written, skipped);
+        if let Err(e) = index.save(store.as_ref()) {
+            log::warn!("write {} fail: {}", HashIndex::FILE, e);
+        }
+        if let Err(e) = store.finish() {
+            log::warn!("finish output {} fail: {}", output.display(), e);
+        }
     }
 }
 
@@ -354,8 +630,6 @@ mod test {
         b.zmin = -43;
         b.zmax = 50;
 
-        let p = PathBuf::new();
-
         for input in &[
             "layer+",
             "layer+:0",
@@ -369,7 +643,7 @@ mod test {
             match PathMode::from_str(input) {
                 Ok(m) => {
                     println!("{:?}", m);
-                    let layer = m.extract(&b, &p);
+                    let layer = m.extract(&b);
                     println!("{:?}", layer.get_max_scale());
                 },
                 Err(e) => {
@@ -379,4 +653,120 @@ mod test {
         }
     }
 
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tree_path() {
+        use super::Bound;
+        use super::PathMode;
+        use std::str::FromStr;
+
+        let mut b = Bound::new();
+        b.xmin = -3;
+        b.xmax = 3;
+        b.zmin = -1;
+        b.zmax = 1;
+
+        assert!(PathMode::from_str("tree:a,b,c").is_err());
+        let tree = PathMode::from_str("tree").unwrap().extract(&b);
+        assert_eq!(tree.get_max_scale(), 2);
+        assert_eq!(tree.generate(-1, -1, 2), "0");
+        assert_eq!(tree.generate(0, 0, 2), "3");
+        assert_eq!(tree.generate(3, -4, 0), "1/1/1");
+        let tree = PathMode::from_str("tree:q,r,s,t").unwrap().extract(&b);
+        assert_eq!(tree.generate(-4, 3, 0), "s/s/s");
+        assert_eq!(tree.generate(-1, 0, 1), "s/r");
+    }
+
+
+    #[test]
+    fn test_xyz_path() {
+        use super::Bound;
+        use super::PathMode;
+        use std::str::FromStr;
+
+        let mut b = Bound::new();
+        b.xmin = -3;
+        b.xmax = 3;
+        b.zmin = -1;
+        b.zmax = 1;
+
+        assert!(PathMode::from_str("xyz:0").is_err());
+        let xyz = PathMode::from_str("xyz").unwrap().extract(&b);
+        assert_eq!(xyz.get_max_scale(), 2);
+        assert_eq!(xyz.generate(-1, 0, 2), "1/0/1");
+        assert_eq!(xyz.generate(-4, 3, 0), "3/0/7");
+        let xyz = PathMode::from_str("xyz:5").unwrap().extract(&b);
+        assert_eq!(xyz.generate(0, 0, 0), "5/16/16");
+        let xyz = PathMode::from_str("xyz:1").unwrap().extract(&b);
+        assert_eq!(xyz.get_max_scale(), 2);
+    }
+
+
+    #[test]
+    fn test_merge_in_memory() {
+        use super::Bound;
+        use super::PathMode;
+        use super::TileWriter;
+        use super::HashIndex;
+        use super::MemoryMeter;
+        use super::list_tiles;
+        use super::merge_branch;
+        use super::generate_into;
+        use super::update_ancestors;
+        use super::tile::TileId;
+        use super::store::MemoryStore;
+        use super::store::TileStore;
+        use crate::encoder::TileEncoder;
+        use std::str::FromStr;
+        use image::Rgba;
+        use image::RgbaImage;
+
+        let source = MemoryStore::new();
+        let img = RgbaImage::from_pixel(4, 4, Rgba::from([200, 10, 10, 255]));
+        for key in &["0,0.png", "1,0.png", "notes.txt"] {
+            source.put(key, TileEncoder::Png.encode(&img).unwrap()).unwrap();
+        }
+        let mut cache = list_tiles(&source);
+        assert_eq!(cache.len(), 2);
+
+        let mut b = Bound::new();
+        b.extend(&TileId::new(0, 1, 0));
+        let path_gen = PathMode::from_str("xyz").unwrap().extract(&b);
+        let output = MemoryStore::new();
+        let index = HashIndex::new();
+        let writer = TileWriter {
+            path_gen: path_gen.as_ref(),
+            store: &output,
+            index: &index,
+            check: false,
+            encoder: TileEncoder::Png,
+        };
+        let meter = MemoryMeter::new();
+        merge_branch(TileId::new(path_gen.get_max_scale(), 0, 0), &mut cache, &source, &writer, image::imageops::FilterType::Nearest, 0, &meter);
+        assert_eq!(meter.current(), 0);
+        assert_eq!(meter.peak(), 4 * 4 * 4 * 7);
+        assert_eq!(output.keys(), vec!["1/1/1.png", "2/2/2.png", "2/3/2.png"]);
+        assert!(output.exists("1/1/1.png"));
+        assert_eq!(output.list("2/3").unwrap(), vec!["2/3/2.png"]);
+
+        let output = MemoryStore::new();
+        let written = generate_into(list_tiles(&source), &source, &output, &PathMode::from_str("xyz").unwrap(), TileEncoder::Png, image::imageops::FilterType::Nearest);
+        assert_eq!(written, 3);
+        assert_eq!(output.keys(), vec!["1/1/1.png", "2/2/2.png", "2/3/2.png", "index.html", "tiles.json"]);
+
+        let blue = RgbaImage::from_pixel(4, 4, Rgba::from([10, 10, 200, 255]));
+        output.put("2/3/2.png", TileEncoder::Png.encode(&blue).unwrap()).unwrap();
+        let writer = TileWriter {
+            path_gen: path_gen.as_ref(),
+            store: &output,
+            index: &index,
+            check: false,
+            encoder: TileEncoder::Png,
+        };
+        update_ancestors(&[TileId::new(0, 1, 0)], &writer, image::imageops::FilterType::Nearest);
+        let top = crate::encoder::decode(output.get("1/1/1.png").unwrap().unwrap().as_slice()).unwrap();
+        assert_eq!(top.get_pixel(0, 0), &Rgba::from([200, 10, 10, 255]));
+        assert_eq!(top.get_pixel(3, 0), &Rgba::from([10, 10, 200, 255]));
+    }
+
+}
@@ -1,17 +1,46 @@
-use std::path::PathBuf;
+use std::io;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::Bound;
+use super::store::TileStore;
 
 
 pub trait PathGenerator {
 
     fn get_max_scale(&self) -> i32;
     
-    fn generate(&self, x: i32, z: i32, scale: i32 /* scale level increase from 0 */) -> PathBuf;
+    /// store key of the tile, without extension
+    fn generate(&self, x: i32, z: i32, scale: i32 /* scale level increase from 0 */) -> String;
+
+    /// write files describing the layout next to the tiles, if the layout needs any;
+    /// `min_scale` is negative when enlarged tiles are generated; `ext` is the extension of the tile files
+    fn write_meta(&self, _bound: &Bound, _min_scale: i32, _ext: &str, _store: &dyn TileStore) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn layout(&self) -> Layout<'_>;
+
+}
 
+
+/// how tile paths are built, for viewers
+#[derive(Serialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Layout<'a> {
+    Layer {
+        start: i32,
+        step: i32,
+    },
+    Tree {
+        names: [&'a str; 4],
+    },
+    Xyz,
 }
 
 
 pub struct Layer {
-    root: PathBuf,
     start: i32,
     step: i32,
     max_scale: i32
@@ -19,9 +48,8 @@ pub struct Layer {
 
 impl Layer {
 
-    pub fn new(start: i32, step: i32, stop: i32, root: PathBuf) -> Self {
+    pub fn new(start: i32, step: i32, stop: i32) -> Self {
         Layer {
-            root,
             start,
             step,
             max_scale: (stop - start) / step
@@ -35,19 +63,26 @@ impl PathGenerator for Layer {
         self.max_scale
     }
 
-    fn generate(&self, x: i32, z: i32, scale: i32) -> PathBuf {
+    fn generate(&self, x: i32, z: i32, scale: i32) -> String {
         let zoom = self.start + self.step * scale;
-        let mut pathbuf = self.root.to_path_buf();
-        pathbuf.push(format!("{}", zoom));
-        pathbuf.push(format!("{},{}.png", x, z));
-        pathbuf
+        format!("{}/{},{}", zoom, x, z)
+    }
+
+    fn layout(&self) -> Layout<'_> {
+        Layout::Layer {
+            start: self.start,
+            step: self.step,
+        }
     }
 
 }
 
 
+/**
+ * quadtree layout: the 4 roots of `merge_branch` are children of one virtual root,
+ * a tile at `scale` is `<d1>/<d2>/.../<dn>.png` with `n = max_scale + 1 - scale`
+ */
 pub struct Tree {
-    root: PathBuf,
     max_scale: i32,
     topleft: String, 
     topright: String, 
@@ -55,3 +90,128 @@ pub struct Tree {
     bottomright: String
 }
 
+impl Tree {
+
+    /// `names` are in order of topleft, topright, bottomleft, bottomright
+    pub fn new(max_scale: i32, names: [String; 4]) -> Self {
+        let [topleft, topright, bottomleft, bottomright] = names;
+        Tree {
+            max_scale,
+            topleft,
+            topright,
+            bottomleft,
+            bottomright,
+        }
+    }
+}
+
+impl PathGenerator for Tree {
+
+    fn get_max_scale(&self) -> i32 {
+        self.max_scale
+    }
+
+    fn generate(&self, x: i32, z: i32, scale: i32) -> String {
+        let depth = self.max_scale + 1 - scale;
+        let offset = 1i64 << (depth - 1);
+        let nx = x as i64 + offset;
+        let nz = z as i64 + offset;
+        let names: Vec<&str> = (0 .. depth).rev().map(|bit| {
+            match ((nx >> bit) & 0x1, (nz >> bit) & 0x1) {
+                (0, 0) => self.topleft.as_str(),
+                (1, 0) => self.topright.as_str(),
+                (0, _) => self.bottomleft.as_str(),
+                _ => self.bottomright.as_str(),
+            }
+        }).collect();
+        names.join("/")
+    }
+
+    fn layout(&self) -> Layout<'_> {
+        Layout::Tree {
+            names: [&self.topleft, &self.topright, &self.bottomleft, &self.bottomright],
+        }
+    }
+
+}
+
+
+#[derive(Serialize)]
+struct XyzMeta<'a> {
+    scheme: &'static str,
+    format: &'a str,
+    tile_size: u32,
+    min_zoom: i32,
+    max_zoom: i32,
+    /// zoom of the original tiles, larger zooms are enlarged
+    native_zoom: i32,
+    /// tile index at `max_zoom` minus the original tile id `x,z`
+    offset: [i64; 2],
+    /// block coordinate of the top-left pixel of tile `0/0` at every zoom
+    origin: [i64; 2],
+    blocks_per_pixel: BTreeMap<i32, f64>,
+    /// original tile ids of the source tiles, inclusive
+    bound: &'a Bound,
+}
+
+/**
+ * slippy-map layout `{z}/{x}/{y}.png` with non-negative indices;
+ * zoom `max_scale + 1` is the original scale, zoom 0 would be one tile of the whole world
+ */
+pub struct Xyz {
+    max_scale: i32,
+    tile_size: u32,
+}
+
+impl Xyz {
+
+    pub const META: &'static str = "tiles.json";
+
+    pub fn new(max_scale: i32, tile_size: u32) -> Self {
+        Xyz {
+            max_scale,
+            tile_size,
+        }
+    }
+
+    fn offset(&self, scale: i32) -> i64 {
+        1i64 << (self.max_scale - scale)
+    }
+}
+
+impl PathGenerator for Xyz {
+
+    fn get_max_scale(&self) -> i32 {
+        self.max_scale
+    }
+
+    fn generate(&self, x: i32, z: i32, scale: i32) -> String {
+        let offset = self.offset(scale);
+        format!("{}/{}/{}", self.max_scale + 1 - scale, x as i64 + offset, z as i64 + offset)
+    }
+
+    fn write_meta(&self, bound: &Bound, min_scale: i32, ext: &str, store: &dyn TileStore) -> io::Result<()> {
+        let native_zoom = self.max_scale + 1;
+        let max_zoom = native_zoom - min_scale;
+        let offset = self.offset(0);
+        let meta = XyzMeta {
+            scheme: "xyz",
+            format: ext,
+            tile_size: self.tile_size,
+            min_zoom: 1,
+            max_zoom,
+            native_zoom,
+            offset: [offset, offset],
+            origin: [-offset * self.tile_size as i64, -offset * self.tile_size as i64],
+            blocks_per_pixel: (1 ..= max_zoom).map(|zoom| (zoom, 2f64.powi(native_zoom - zoom))).collect(),
+            bound,
+        };
+        let data = serde_json::to_vec_pretty(&meta).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        store.put(Self::META, data)
+    }
+
+    fn layout(&self) -> Layout<'_> {
+        Layout::Xyz
+    }
+
+}
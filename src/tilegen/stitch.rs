@@ -0,0 +1,269 @@
+use std::io;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::io::BufWriter;
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::collections::HashMap;
+
+use image::RgbaImage;
+use image::imageops;
+use image::imageops::FilterType;
+
+use super::list_tiles;
+use super::Bound;
+use super::tile::LoadableImage;
+use super::tile::TileId;
+use super::tile::load_image;
+use super::store::DirStore;
+use super::store::TileStore;
+
+
+/// `max` or `xmin,zmin,xmax,zmax` (inclusive, `,` or space separated)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StitchRange {
+    Max,
+    Fixed {
+        xmin: i32,
+        zmin: i32,
+        xmax: i32,
+        zmax: i32,
+    },
+}
+
+impl FromStr for StitchRange {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "max" {
+            return Ok(Self::Max);
+        }
+        let err = || io::Error::new(io::ErrorKind::InvalidInput, "expected \"max\" or <xmin>,<zmin>,<xmax>,<zmax> with min <= max");
+        let v = s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|p| !p.is_empty())
+            .map(|p| p.parse().map_err(|_| err()))
+            .collect::<Result<Vec<i32>, _>>()?;
+        if v.len() != 4 || v[0] > v[2] || v[1] > v[3] {
+            return Err(err());
+        }
+        Ok(Self::Fixed { xmin: v[0], zmin: v[1], xmax: v[2], zmax: v[3] })
+    }
+}
+
+
+pub struct StitchOptions {
+    input_folder: PathBuf,
+    output_file: PathBuf,
+    range: StitchRange,
+    downscale: u32,
+    filter: FilterType,
+}
+
+impl StitchOptions {
+
+    pub fn new(input_folder: PathBuf, output_file: PathBuf, range: StitchRange) -> Self {
+        StitchOptions {
+            input_folder,
+            output_file,
+            range,
+            downscale: 1,
+            filter: FilterType::Triangle,
+        }
+    }
+
+    pub fn set_downscale(&mut self, downscale: u32) {
+        if downscale > 0 {
+            self.downscale = downscale;
+        }
+    }
+
+    pub fn set_filter(&mut self, filter: &str) {
+        match filter {
+            "nearest" => self.filter = FilterType::Nearest,
+            "triangle" => self.filter = FilterType::Triangle,
+            "gaussian" => self.filter = FilterType::Gaussian,
+            "catmullrom" => self.filter = FilterType::CatmullRom,
+            "lanczos3" => self.filter = FilterType::Lanczos3,
+            _ => { }
+        }
+    }
+}
+
+
+fn load_tile(store: &dyn TileStore, image: &LoadableImage) -> Option<RgbaImage> {
+    if let LoadableImage::Unloaded(key) = image {
+        match load_image(store, key) {
+            Ok(img) => Some(img),
+            Err(e) => {
+                log::warn!("load {} fail: {}", key, e);
+                None
+            }
+        }
+    } else {
+        None
+    }
+}
+
+
+/**
+ * stitch `x,z.png` (or `.webp`) tiles into one png; tiles are loaded and written one tile-row at a time
+ */
+pub struct Stitcher {
+    options: StitchOptions,
+}
+
+impl Stitcher {
+
+    pub fn new(options: StitchOptions) -> Self {
+        Stitcher {
+            options,
+        }
+    }
+
+    /// returns the size of the output image
+    pub fn stitch(&self) -> io::Result<(u32, u32)> {
+        let source = DirStore::new(self.options.input_folder.clone());
+        let mut tiles = list_tiles(&source);
+        let (xmin, zmin, xmax, zmax) = match self.options.range {
+            StitchRange::Max => {
+                let mut bound = Bound::new();
+                let mut first = true;
+                for tile in tiles.keys() {
+                    if first {
+                        bound = Bound { xmin: tile.x, xmax: tile.x, zmin: tile.z, zmax: tile.z };
+                        first = false;
+                    }
+                    bound.extend(tile);
+                }
+                if first {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "no tile"));
+                }
+                (bound.xmin, bound.zmin, bound.xmax, bound.zmax)
+            }
+            StitchRange::Fixed { xmin, zmin, xmax, zmax } => (xmin, zmin, xmax, zmax),
+        };
+        tiles.retain(|t, _| t.x >= xmin && t.x <= xmax && t.z >= zmin && t.z <= zmax);
+
+        // tile size is taken from any tile in range
+        let tile_size = tiles.values().find_map(|image| load_tile(&source, image))
+            .map(|img| img.width())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no tile in range"))?;
+        let downscale = self.options.downscale;
+        if tile_size % downscale != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("downscale {} does not divide tile size {}", downscale, tile_size)));
+        }
+        let part = tile_size / downscale;
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "output too large");
+        let span = |min: i32, max: i32| u32::try_from(max as i64 - min as i64 + 1).ok();
+        let columns = span(xmin, xmax).ok_or_else(too_large)?;
+        let rows = span(zmin, zmax).ok_or_else(too_large)?;
+        let width = columns.checked_mul(part).ok_or_else(too_large)?;
+        let height = rows.checked_mul(part).ok_or_else(too_large)?;
+        let band_bytes = (width as usize).checked_mul(4).and_then(|b| b.checked_mul(part as usize)).ok_or_else(too_large)?;
+        log::info!("> stitch {}x{} tiles into {}x{}", columns, rows, width, height);
+
+        let ofile = BufWriter::new(File::create(&self.options.output_file)?);
+        let mut encoder = png::Encoder::new(ofile, width, height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let encode_error = |e: png::EncodingError| io::Error::new(io::ErrorKind::Other, e.to_string());
+        let mut writer = encoder.write_header().map_err(encode_error)?;
+        let mut stream = writer.stream_writer();
+
+        let row_bytes = width as usize * 4;
+        let part_bytes = part as usize * 4;
+        let mut band = vec![0u8; band_bytes];
+        for z in zmin ..= zmax {
+            band.iter_mut().for_each(|b| *b = 0);
+            let mut row_tiles: HashMap<i32, LoadableImage> = HashMap::new();
+            for x in xmin ..= xmax {
+                if let Some(image) = tiles.remove(&TileId::new(0, x, z)) {
+                    row_tiles.insert(x, image);
+                }
+            }
+            for (x, image) in row_tiles {
+                let img = match load_tile(&source, &image) {
+                    Some(img) => img,
+                    None => continue,
+                };
+                if img.width() != tile_size || img.height() != tile_size {
+                    log::warn!("tile ({},{}) skipped: size {}x{}", x, z, img.width(), img.height());
+                    continue;
+                }
+                let img = if downscale > 1 {
+                    imageops::resize(&img, part, part, self.options.filter)
+                } else {
+                    img
+                };
+                let offset = (x as i64 - xmin as i64) as usize * part_bytes;
+                for (dy, line) in img.as_raw().chunks(part_bytes).enumerate() {
+                    let start = dy * row_bytes + offset;
+                    band[start .. start + part_bytes].copy_from_slice(line);
+                }
+            }
+            stream.write_all(band.as_slice())?;
+            log::info!("row {} done", z);
+        }
+        stream.finish().map_err(encode_error)?;
+        Ok((width, height))
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_stitch_range_parse() {
+        use super::StitchRange;
+        use std::str::FromStr;
+
+        assert_eq!(StitchRange::from_str("max").unwrap(), StitchRange::Max);
+        assert_eq!(
+            StitchRange::from_str("-2,-3 4,5").unwrap(),
+            StitchRange::Fixed { xmin: -2, zmin: -3, xmax: 4, zmax: 5 }
+        );
+        assert!(StitchRange::from_str("1,2,3").is_err());
+        assert!(StitchRange::from_str("4,0,1,0").is_err());
+    }
+
+    #[test]
+    fn test_stitch() {
+        use image::Rgba;
+        use image::RgbaImage;
+        use super::StitchOptions;
+        use super::StitchRange;
+        use super::Stitcher;
+
+        let dir = std::env::temp_dir().join(format!("voxelmap_test_stitch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tiles = [(-1, 0, [255, 0, 0, 255]), (0, 0, [0, 255, 0, 255]), (0, 1, [0, 0, 255, 255])];
+        for (x, z, color) in tiles.iter() {
+            RgbaImage::from_pixel(4, 4, Rgba(*color)).save(dir.join(format!("{},{}.png", x, z))).unwrap();
+        }
+        let output = dir.join("out.png");
+
+        let stitcher = Stitcher::new(StitchOptions::new(dir.clone(), output.clone(), StitchRange::Max));
+        assert_eq!(stitcher.stitch().unwrap(), (8, 8));
+        let img = image::open(&output).unwrap().to_rgba8();
+        assert_eq!(img.dimensions(), (8, 8));
+        assert_eq!(img.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(img.get_pixel(7, 3), &Rgba([0, 255, 0, 255]));
+        // (-1, 1) is missing
+        assert_eq!(img.get_pixel(3, 4), &Rgba([0, 0, 0, 0]));
+        assert_eq!(img.get_pixel(4, 7), &Rgba([0, 0, 255, 255]));
+
+        let mut options = StitchOptions::new(dir.clone(), output.clone(), StitchRange::Fixed { xmin: 0, zmin: 0, xmax: 0, zmax: 1 });
+        options.set_downscale(2);
+        options.set_filter("nearest");
+        assert_eq!(Stitcher::new(options).stitch().unwrap(), (2, 4));
+        let img = image::open(&output).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(1, 1), &Rgba([0, 255, 0, 255]));
+        assert_eq!(img.get_pixel(0, 2), &Rgba([0, 0, 255, 255]));
+
+        let range = StitchRange::Fixed { xmin: i32::MIN, zmin: 0, xmax: i32::MAX, zmax: 0 };
+        assert!(Stitcher::new(StitchOptions::new(dir.clone(), output, range)).stitch().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+}
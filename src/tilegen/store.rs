@@ -0,0 +1,485 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::ffi::OsString;
+#[cfg(any(test, feature = "service"))]
+use std::collections::BTreeMap;
+
+use zip::ZipArchive;
+use zip::ZipWriter;
+use zip::CompressionMethod;
+use zip::write::FileOptions;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::params;
+
+
+/// folder of the key, `""` for keys at the top level
+fn parent(key: &str) -> &str {
+    key.rfind('/').map(|i| &key[0 .. i]).unwrap_or("")
+}
+
+/**
+ * storage of tiles and the files next to them; keys are relative paths separated by `/`
+ */
+pub trait TileStore: Send + Sync {
+
+    /// `None` if the key does not exist
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()>;
+
+    fn exists(&self, key: &str) -> bool;
+
+    /// keys directly in the folder `prefix`; `""` lists the top level
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    /// the value stored before is unchanged and is kept, for stores that are rewritten on every run
+    fn keep(&self, _key: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// called once after all tiles are written
+    fn finish(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
+
+
+/// one file for each key in a local folder
+pub struct DirStore {
+    root: PathBuf,
+}
+
+impl DirStore {
+
+    pub fn new(root: PathBuf) -> Self {
+        DirStore {
+            root,
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut path = self.root.to_path_buf();
+        for part in key.split('/').filter(|p| !p.is_empty()) {
+            path.push(part);
+        }
+        path
+    }
+}
+
+impl TileStore for DirStore {
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, data)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.path(key).is_file()
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let read_dir = match self.path(prefix).read_dir() {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut keys = Vec::new();
+        for entry in read_dir {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if prefix.is_empty() {
+                    keys.push(String::from(name));
+                } else {
+                    keys.push(format!("{}/{}", prefix.trim_end_matches('/'), name));
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+}
+
+
+/// everything in memory, for tests and short-lived outputs
+#[cfg(any(test, feature = "service"))]
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+#[cfg(any(test, feature = "service"))]
+impl MemoryStore {
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// all keys, sorted
+    pub fn keys(&self) -> Vec<String> {
+        self.data.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[cfg(any(test, feature = "service"))]
+impl TileStore for MemoryStore {
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.data.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        self.data.lock().unwrap().insert(String::from(key), data);
+        Ok(())
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.data.lock().unwrap().contains_key(key)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let prefix = prefix.trim_end_matches('/');
+        Ok(self.data.lock().unwrap().keys().filter(|k| parent(k) == prefix).cloned().collect())
+    }
+
+}
+
+
+/**
+ * all keys in one zip archive, stored without compression; the archive is rewritten on every run into `<file>.tmp`,
+ * which replaces it by `finish`, so a failed run leaves it untouched; reads see the archive before this run when `check` is set
+ */
+pub struct ZipStore {
+    file: PathBuf,
+    tmp_file: PathBuf,
+    old: Mutex<Option<ZipArchive<File>>>,
+    writer: Mutex<Option<ZipWriter<File>>>,
+}
+
+impl ZipStore {
+
+    pub fn create(file: &Path, check: bool) -> io::Result<Self> {
+        let old = if check && file.is_file() {
+            Some(ZipArchive::new(File::open(file)?)?)
+        } else {
+            None
+        };
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_file = OsString::from(file.as_os_str());
+        tmp_file.push(".tmp");
+        let tmp_file = PathBuf::from(tmp_file);
+        let writer = ZipWriter::new(File::create(&tmp_file)?);
+        Ok(ZipStore {
+            file: PathBuf::from(file),
+            tmp_file,
+            old: Mutex::new(old),
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+}
+
+impl TileStore for ZipStore {
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut old = self.old.lock().unwrap();
+        let old = match old.as_mut() {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        let mut entry = match old.by_name(key) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut buf)?;
+        Ok(Some(buf))
+    }
+
+    fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let writer = writer.as_mut().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "archive finished"))?;
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file(key, options)?;
+        writer.write_all(data.as_slice())
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        let mut old = self.old.lock().unwrap();
+        old.as_mut().map(|old| old.by_name(key).is_ok()).unwrap_or(false)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let prefix = prefix.trim_end_matches('/');
+        let old = self.old.lock().unwrap();
+        Ok(old.iter().flat_map(|old| old.file_names()).filter(|k| parent(k) == prefix).map(String::from).collect())
+    }
+
+    fn keep(&self, key: &str) -> io::Result<()> {
+        let mut old = self.old.lock().unwrap();
+        let old = old.as_mut().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no old archive"))?;
+        let entry = old.by_name(key)?;
+        let mut writer = self.writer.lock().unwrap();
+        let writer = writer.as_mut().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "archive finished"))?;
+        writer.raw_copy_file(entry)?;
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.lock().unwrap().take() {
+            writer.finish()?;
+        }
+        self.old.lock().unwrap().take();
+        fs::rename(&self.tmp_file, &self.file)?;
+        log::info!("> archive {} finished", self.file.display());
+        Ok(())
+    }
+
+}
+
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/**
+ * MBTiles (sqlite) with the TMS scheme; keys are `{z}/{x}/{y}.<ext>` of the `xyz` path mode,
+ * stored with `tile_row = 2^z - 1 - y`; other keys, such as `tiles.json` and the hash index, are in a side table `files`
+ */
+pub struct MBTilesStore {
+    format: String,
+    conn: Mutex<Connection>,
+}
+
+impl MBTilesStore {
+
+    pub fn open(file: &Path, min_zoom: i32, max_zoom: i32, format: &str) -> io::Result<Self> {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(file).map_err(sql_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS metadata (name TEXT, value TEXT);
+            CREATE UNIQUE INDEX IF NOT EXISTS name ON metadata (name);
+            CREATE TABLE IF NOT EXISTS tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+            CREATE UNIQUE INDEX IF NOT EXISTS tile_index ON tiles (zoom_level, tile_column, tile_row);
+            CREATE TABLE IF NOT EXISTS files (name TEXT PRIMARY KEY, data BLOB);
+            BEGIN;"
+        ).map_err(sql_error)?;
+        let name = file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let metadata = [
+            ("name", name),
+            ("format", String::from(format)),
+            ("type", String::from("baselayer")),
+            ("minzoom", format!("{}", min_zoom)),
+            ("maxzoom", format!("{}", max_zoom)),
+        ];
+        for (key, value) in metadata.iter() {
+            conn.execute("INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2)", params![key, value])
+                .map_err(sql_error)?;
+        }
+        Ok(MBTilesStore {
+            format: String::from(format),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// `(zoom_level, tile_column, tile_row)` of the key, `None` if it is not a tile
+    pub fn tms(key: &str) -> Option<(i32, i64, i64)> {
+        let mut sp = key.split('/');
+        let zoom: i32 = sp.next()?.parse().ok()?;
+        let column: i64 = sp.next()?.parse().ok()?;
+        let y: i64 = sp.next()?.split('.').next()?.parse().ok()?;
+        if sp.next().is_some() || !(0 .. 62).contains(&zoom) {
+            return None;
+        }
+        Some((zoom, column, (1i64 << zoom) - 1 - y))
+    }
+
+    fn key(&self, zoom: i32, column: i64, row: i64) -> String {
+        format!("{}/{}/{}.{}", zoom, column, (1i64 << zoom) - 1 - row, self.format)
+    }
+}
+
+impl TileStore for MBTilesStore {
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        let (zoom, column, row) = match Self::tms(key) {
+            Some(tms) => tms,
+            None => {
+                return conn.query_row("SELECT data FROM files WHERE name = ?1", params![key], |r| r.get(0))
+                    .optional()
+                    .map_err(sql_error);
+            }
+        };
+        conn.query_row(
+            "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            params![zoom, column, row],
+            |r| r.get(0)
+        ).optional().map_err(sql_error)
+    }
+
+    fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let (zoom, column, row) = match Self::tms(key) {
+            Some(tms) => tms,
+            None => {
+                conn.execute("INSERT OR REPLACE INTO files (name, data) VALUES (?1, ?2)", params![key, data])
+                    .map_err(sql_error)?;
+                return Ok(());
+            }
+        };
+        conn.execute(
+            "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+            params![zoom, column, row, data]
+        ).map_err(sql_error)?;
+        Ok(())
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        matches!(self.get(key), Ok(Some(_)))
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let prefix = prefix.trim_end_matches('/');
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT zoom_level, tile_column, tile_row FROM tiles").map_err(sql_error)?;
+        let rows = stmt.query_map(params![], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))).map_err(sql_error)?;
+        let mut keys = Vec::new();
+        for row in rows {
+            let (zoom, column, row) = row.map_err(sql_error)?;
+            let key = self.key(zoom, column, row);
+            if parent(&key) == prefix {
+                keys.push(key);
+            }
+        }
+        let mut stmt = conn.prepare("SELECT name FROM files").map_err(sql_error)?;
+        let names = stmt.query_map(params![], |r| r.get::<_, String>(0)).map_err(sql_error)?;
+        for name in names {
+            let name = name.map_err(sql_error)?;
+            if parent(&name) == prefix {
+                keys.push(name);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        self.conn.lock().unwrap().execute_batch("COMMIT;").map_err(sql_error)
+    }
+
+}
+
+
+/// `dir` (default), `zip` or `mbtiles`; the output of single-file stores is a file instead of a folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StoreMode {
+    #[default]
+    Dir,
+    Zip,
+    MBTiles,
+}
+
+impl FromStr for StoreMode {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dir" => Ok(StoreMode::Dir),
+            "zip" => Ok(StoreMode::Zip),
+            "mbtiles" => Ok(StoreMode::MBTiles),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported")),
+        }
+    }
+}
+
+impl StoreMode {
+
+    pub fn open(&self, output: &Path, min_zoom: i32, max_zoom: i32, format: &str, check: bool) -> io::Result<Arc<dyn TileStore>> {
+        match self {
+            StoreMode::Dir => Ok(Arc::new(DirStore::new(PathBuf::from(output)))),
+            StoreMode::Zip => Ok(Arc::new(ZipStore::create(output, check)?)),
+            StoreMode::MBTiles => Ok(Arc::new(MBTilesStore::open(output, min_zoom, max_zoom, format)?)),
+        }
+    }
+}
+
+
+mod test {
+
+    #[test]
+    fn test_mbtiles_store() {
+        use super::MBTilesStore;
+        use super::TileStore;
+
+        assert_eq!(MBTilesStore::tms("1/0/0.png"), Some((1, 0, 1)));
+        assert_eq!(MBTilesStore::tms("3/0/7.png"), Some((3, 0, 0)));
+        assert_eq!(MBTilesStore::tms("tiles.json"), None);
+
+        let file = std::env::temp_dir().join("voxelmap_test_store.mbtiles");
+        let _ = std::fs::remove_file(&file);
+        let store = MBTilesStore::open(file.as_path(), 1, 3, "png").unwrap();
+        assert!(!store.exists("2/3/1.png"));
+        store.put("2/3/1.png", vec![1, 2, 3]).unwrap();
+        assert_eq!(store.get("2/3/1.png").unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(store.list("2/3").unwrap(), vec![String::from("2/3/1.png")]);
+        assert!(store.list("2").unwrap().is_empty());
+        store.put("tiles.json", vec![4]).unwrap();
+        assert_eq!(store.get("tiles.json").unwrap(), Some(vec![4]));
+        assert!(store.exists("tiles.json"));
+        assert_eq!(store.list("").unwrap(), vec![String::from("tiles.json")]);
+        store.finish().unwrap();
+        drop(store);
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn test_zip_store() {
+        use super::ZipStore;
+        use super::TileStore;
+
+        let file = std::env::temp_dir().join(format!("voxelmap_test_store_{}.zip", std::process::id()));
+        let store = ZipStore::create(file.as_path(), true).unwrap();
+        store.put("0/0/0.png", vec![1]).unwrap();
+        store.finish().unwrap();
+
+        // a run stopped before `finish` keeps the archive of the last one
+        let store = ZipStore::create(file.as_path(), true).unwrap();
+        assert_eq!(store.get("0/0/0.png").unwrap(), Some(vec![1]));
+        store.put("0/0/0.png", vec![2]).unwrap();
+        drop(store);
+        let store = ZipStore::create(file.as_path(), true).unwrap();
+        assert_eq!(store.get("0/0/0.png").unwrap(), Some(vec![1]));
+        store.keep("0/0/0.png").unwrap();
+        store.finish().unwrap();
+        drop(store);
+        let _ = std::fs::remove_file(&file);
+    }
+
+}
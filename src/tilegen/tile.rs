@@ -1,25 +1,28 @@
-use std::path::Path;
-use std::path::PathBuf;
-use std::fs;
-use std::fs::File;
+use std::io;
 use std::hash::Hash;
+use std::collections::HashSet;
 use std::ops::Deref;
-use std::cmp::Ordering;
 
 use image;
 use image::Pixel;
-use image::DynamicImage;
 use image::ImageBuffer;
 use image::RgbaImage;
-use image::ImageFormat;
 use image::ImageResult;
 use image::ImageError;
 use image::imageops;
 use image::imageops::FilterType;
 
+use crate::encoder::TileEncoder;
+use crate::encoder::decode;
+use crate::encoder::same_pixels;
+use super::store::TileStore;
+use super::index::HashIndex;
+use super::index::pixel_hash;
+
 
 pub enum LoadableImage {
-    Unloaded(PathBuf),
+    /// key in the source store
+    Unloaded(String),
     Image(RgbaImage),
     Empty,
 }
@@ -31,11 +34,10 @@ impl Default for LoadableImage {
     }
 }
 
-fn load_png<P: AsRef<Path>>(path: P) -> ImageResult<DynamicImage> {
-    use std::io::BufReader;
-
-    let ifile = File::open(path).map_err(ImageError::from)?;
-    image::load(BufReader::new(ifile), ImageFormat::Png)
+pub fn load_image(store: &dyn TileStore, key: &str) -> ImageResult<RgbaImage> {
+    let data = store.get(key).map_err(ImageError::from)?
+        .ok_or_else(|| ImageError::IoError(io::Error::new(io::ErrorKind::NotFound, key)))?;
+    decode(data.as_slice())
 }
 
 fn cmp_image_buffer<P, C1, C2>(img1: &ImageBuffer<P, C1>, img2: &ImageBuffer<P, C2>) -> bool 
@@ -54,8 +56,8 @@ fn cmp_image_buffer<P, C1, C2>(img1: &ImageBuffer<P, C1>, img2: &ImageBuffer<P,
 
 impl LoadableImage {
 
-    pub fn new(path: PathBuf) -> Self {
-        Self::Unloaded(path)
+    pub fn new(key: String) -> Self {
+        Self::Unloaded(key)
     }
 
     #[inline]
@@ -66,19 +68,23 @@ impl LoadableImage {
         }
     }
 
-    pub fn ensure(&mut self) {
+    /// bytes of the decoded pixels, 0 if not loaded
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::Image(img) => img.as_raw().len(),
+            _ => 0,
+        }
+    }
+
+    pub fn ensure(&mut self, store: &dyn TileStore) {
         
 
-        if let Self::Unloaded(path) = self {
-            let img = if let Ok(image) = load_png(path.as_path()) {
-                if let DynamicImage::ImageRgba8(image) = image {
-                    if image.width() != image.height() {
-                        Self::Empty
-                    } else {
-                        Self::Image(image)
-                    }
-                } else {
+        if let Self::Unloaded(key) = self {
+            let img = if let Ok(image) = load_image(store, key) {
+                if image.width() != image.height() {
                     Self::Empty
+                } else {
+                    Self::Image(image)
                 }
             } else {
                 Self::Empty
@@ -87,46 +93,36 @@ impl LoadableImage {
         }
     }
 
-    pub fn save(&self, path: &PathBuf, check: bool) -> ImageResult<bool> {
-        use image::buffer::ConvertBuffer;
-        use image::RgbImage;
-
+    /// `check`: keep the tile in the store if its hash in `index` is unchanged;
+    /// a tile not in the index is compared with the stored one by pixels (lossless) or content (lossy)
+    pub fn save(&self, key: &str, store: &dyn TileStore, index: &HashIndex, check: bool, encoder: TileEncoder) -> ImageResult<bool> {
         if let Self::Image(img) = self {
-            let mut full = true;
-            for pixel in img.pixels() {
-                if pixel[3] < 255 {
-                    full = false;
-                    break;
-                }
-            }
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent).map_err(ImageError::from)?;
-            }
-            if full {
-                let img: RgbImage = img.convert();
-                if check {
-                    if let Ok(old) = load_png(path.as_path()) {
-                        if let DynamicImage::ImageRgb8(old) = &old {
-                            if img.cmp(old) == Ordering::Equal {
-                                return Ok(false);
-                            }
-                        }
+            let hash = pixel_hash(img, encoder);
+            let known = if check { index.unchanged(key, hash) } else { Some(false) };
+            let mut unchanged = known == Some(true) && store.exists(key);
+            let mut data = None;
+            if known.is_none() {
+                if let Some(old) = store.get(key).ok().flatten() {
+                    if encoder.is_lossless() {
+                        unchanged = decode(old.as_slice()).map(|old| same_pixels(img, &old)).unwrap_or(false);
+                    } else {
+                        let encoded = encoder.encode(img)?;
+                        unchanged = encoded == old;
+                        data = Some(encoded);
                     }
                 }
-                img.save_with_format(path.as_path(), ImageFormat::Png)?;
+            }
+            if unchanged {
+                store.keep(key).map_err(ImageError::from)?;
             } else {
-                if check {
-                    if let Ok(old) = load_png(path.as_path()) {
-                        if let DynamicImage::ImageRgba8(old) = &old {
-                            if img.cmp(old) == Ordering::Equal {
-                                return Ok(false);
-                            }
-                        }
-                    }
-                }
-                img.save_with_format(path.as_path(), ImageFormat::Png)?;
+                let data = match data {
+                    Some(data) => data,
+                    None => encoder.encode(img)?,
+                };
+                store.put(key, data).map_err(ImageError::from)?;
             }
-            Ok(true)
+            index.record(key, hash, !unchanged);
+            Ok(!unchanged)
         } else {
             Ok(false)
         }
@@ -159,6 +155,22 @@ impl LoadableImage {
         }
     }
 
+    /// part `(i, j)` of the image cut into `2^level` x `2^level`, enlarged back to the full size
+    pub fn zoom_in(&self, level: u32, i: u32, j: u32) -> Self {
+        if let Self::Image(img) = self {
+            let n = 1 << level;
+            let w = img.width() / n;
+            let h = img.height() / n;
+            if w * h == 0 {
+                return Self::Empty;
+            }
+            let part = imageops::crop_imm(img, i * w, j * h, w, h).to_image();
+            Self::Image(imageops::resize(&part, img.width(), img.height(), FilterType::Nearest))
+        } else {
+            Self::Empty
+        }
+    }
+
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
@@ -219,8 +231,8 @@ impl TileId {
     
     pub fn parent(&self) -> Self {
         TileId {
-            x: self.x / 2,
-            z: self.z / 2,
+            x: self.x >> 1,
+            z: self.z >> 1,
             scale: self.scale + 1,
         }
     }
@@ -242,39 +254,58 @@ impl TileId {
 }
 
 
-#[derive(Clone)]
 struct TileQTreeIndexNode {
     tile_id: TileId,
-    parent_index: isize,
-    child_id: usize,
+    expanded: bool,
 }
 
 impl TileQTreeIndexNode {
-    pub fn new(tile_id: TileId, parent_index: isize) -> Self {
+    pub fn new(tile_id: TileId) -> Self {
         TileQTreeIndexNode {
             tile_id,
-            parent_index,
-            child_id: 0
+            expanded: false,
         }
     }
 }
 
 
+/// children before parents, depth first
 pub struct TileQTreeIterator {
     stack: Vec<TileQTreeIndexNode>,
     stop_layer: i32,
+    tiles: Option<HashSet<TileId>>,
 }
 
 impl TileQTreeIterator {
 
     pub fn new(root: TileId, stop_layer: i32) -> Self {
-        let mut stack = Vec::new();
-        stack.push(TileQTreeIndexNode::new(root, -1));
+        TileQTreeIterator {
+            stack: vec![TileQTreeIndexNode::new(root)],
+            stop_layer,
+            tiles: None,
+        }
+    }
+
+    /// only the nodes with at least one of `tiles` below them (or being one of them)
+    pub fn over<'a, I: IntoIterator<Item = &'a TileId>>(root: TileId, stop_layer: i32, tiles: I) -> Self {
+        let mut set = HashSet::new();
+        for tile in tiles {
+            let mut tile = tile.clone();
+            while tile.scale <= root.scale && set.insert(tile.clone()) {
+                tile = tile.parent();
+            }
+        }
+        let stack = if set.contains(&root) { vec![TileQTreeIndexNode::new(root)] } else { Vec::new() };
         TileQTreeIterator {
             stack,
-            stop_layer
+            stop_layer,
+            tiles: Some(set),
         }
     }
+
+    fn contains(&self, tile_id: &TileId) -> bool {
+        self.tiles.as_ref().map(|tiles| tiles.contains(tile_id)).unwrap_or(true)
+    }
 }
 
 impl Iterator for TileQTreeIterator {
@@ -282,20 +313,16 @@ impl Iterator for TileQTreeIterator {
 
     fn next(&mut self) -> Option<Self::Item> { 
         loop {
-            let TileQTreeIndexNode{ tile_id, parent_index, child_id } = self.stack.last()?.clone();
-            let current_index = self.stack.len() as isize - 1;
-            if tile_id.scale <= self.stop_layer || child_id > 3 {
-                if parent_index >= 0 {
-                    let parent = &mut self.stack[parent_index as usize];
-                    parent.child_id += 1;
+            let top = self.stack.last_mut()?;
+            if top.expanded || top.tile_id.scale <= self.stop_layer {
+                return self.stack.pop().map(|node| node.tile_id);
+            }
+            top.expanded = true;
+            let tile_id = top.tile_id.clone();
+            for child in [tile_id.bottomright(), tile_id.bottomleft(), tile_id.topright(), tile_id.topleft()] {
+                if self.contains(&child) {
+                    self.stack.push(TileQTreeIndexNode::new(child));
                 }
-                self.stack.pop();
-                return Some(tile_id);
-            } else {
-                self.stack.push(TileQTreeIndexNode::new(tile_id.bottomright(), current_index));
-                self.stack.push(TileQTreeIndexNode::new(tile_id.bottomleft(), current_index));
-                self.stack.push(TileQTreeIndexNode::new(tile_id.topright(), current_index));
-                self.stack.push(TileQTreeIndexNode::new(tile_id.topleft(), current_index));
             }
         }
     }
@@ -316,4 +343,39 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_tile_qtree_over() {
+        use super::{TileId, TileQTreeIterator};
+
+        let tiles = [TileId::new(0, -1, -3), TileId::new(0, -2, -3)];
+        let visited: Vec<TileId> = TileQTreeIterator::over(TileId::new(2, -1, -1), 0, tiles.iter()).collect();
+        assert_eq!(visited, vec![
+            TileId::new(0, -2, -3),
+            TileId::new(0, -1, -3),
+            TileId::new(1, -1, -2),
+            TileId::new(2, -1, -1),
+        ]);
+        assert_eq!(TileQTreeIterator::over(TileId::new(2, 0, 0), 0, tiles.iter()).count(), 0);
+        assert_eq!(TileQTreeIterator::new(TileId::new(2, 0, 0), 0).count(), 21);
+    }
+
+
+    #[test]
+    fn test_zoom_in() {
+        use super::LoadableImage;
+        use image::Rgba;
+        use image::RgbaImage;
+
+        let img = RgbaImage::from_fn(8, 8, |x, y| Rgba::from([x as u8, y as u8, 0, 255]));
+        let img = LoadableImage::Image(img);
+        if let LoadableImage::Image(part) = img.zoom_in(2, 3, 1) {
+            assert_eq!(part.dimensions(), (8, 8));
+            assert_eq!(part.get_pixel(0, 0), &Rgba::from([6, 2, 0, 255]));
+            assert_eq!(part.get_pixel(7, 7), &Rgba::from([7, 3, 0, 255]));
+        } else {
+            panic!("zoom_in gives no image");
+        }
+        assert!(!img.zoom_in(4, 0, 0).is_image());
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+use std::io;
+
+use serde::Serialize;
+
+use super::Bound;
+use super::pathgen::Layout;
+use super::pathgen::PathGenerator;
+use super::store::TileStore;
+
+
+const TEMPLATE: &str = include_str!("viewer.html");
+
+pub const VIEWER: &str = "index.html";
+
+
+#[derive(Serialize)]
+struct ViewerConfig<'a> {
+    layout: Layout<'a>,
+    max_scale: i32,
+    min_scale: i32,
+    tile_size: u32,
+    ext: &'a str,
+    min_zoom: i32,
+    max_zoom: i32,
+    max_native_zoom: i32,
+    bound: &'a Bound,
+}
+
+/// leaflet page showing the tiles of `path_gen`, written to `index.html` of the store
+pub fn write_viewer(path_gen: &dyn PathGenerator, store: &dyn TileStore, bound: &Bound, tile_size: u32, overzoom: u32, ext: &str) -> io::Result<()> {
+    let max_scale = path_gen.get_max_scale();
+    let config = ViewerConfig {
+        layout: path_gen.layout(),
+        max_scale,
+        min_scale: -(overzoom as i32),
+        tile_size,
+        ext,
+        min_zoom: -max_scale,
+        max_zoom: overzoom as i32,
+        max_native_zoom: overzoom as i32,
+        bound,
+    };
+    let config = serde_json::to_string(&config).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let html = TEMPLATE.replace("/*CONFIG*/", config.replace("</", "<\\/").as_str());
+    store.put(VIEWER, html.into_bytes())
+}